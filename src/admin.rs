@@ -0,0 +1,119 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::lichess::{Lichess, Retry};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AdminConfig {
+    pub addr: SocketAddr,
+    // expected as `Authorization: Bearer {token}`
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+struct PerfQuery {
+    perf: String,
+}
+
+async fn reports(State(lichess): State<Arc<Lichess>>) -> impl IntoResponse {
+    Json(lichess.recent_reports())
+}
+
+async fn arenas(State(lichess): State<Arc<Lichess>>) -> impl IntoResponse {
+    Json(lichess.last_arenas())
+}
+
+async fn status(State(lichess): State<Arc<Lichess>>) -> impl IntoResponse {
+    Json(json!({ "paused": lichess.is_paused(), "status": lichess.status_message() }))
+}
+
+// Screening an arena takes a while, so it is done in the background
+async fn scan_arena(
+    State(lichess): State<Arc<Lichess>>,
+    Path(arena_id): Path<String>,
+) -> impl IntoResponse {
+    match lichess.get_arena(&arena_id, Retry::Never).await {
+        Ok(arena) => {
+            info!("Screening arena {arena_id} on demand");
+            tokio::spawn(async move { lichess.screen_arena(&arena).await });
+            StatusCode::ACCEPTED
+        }
+        Err(err) if err.is_not_found() => StatusCode::NOT_FOUND,
+        Err(err) => {
+            warn!("Could not get arena {arena_id}: {err}");
+            StatusCode::BAD_GATEWAY
+        }
+    }
+}
+
+async fn scan_user(
+    State(lichess): State<Arc<Lichess>>,
+    Path(user_id): Path<String>,
+    Query(query): Query<PerfQuery>,
+) -> impl IntoResponse {
+    lichess
+        .check_user(&user_id.to_lowercase(), &query.perf)
+        .await
+}
+
+async fn pause(State(lichess): State<Arc<Lichess>>) -> impl IntoResponse {
+    lichess.set_paused(true);
+    StatusCode::NO_CONTENT
+}
+
+async fn resume(State(lichess): State<Arc<Lichess>>) -> impl IntoResponse {
+    lichess.set_paused(false);
+    StatusCode::NO_CONTENT
+}
+
+async fn auth(State(token): State<Arc<String>>, req: Request, next: Next) -> Response {
+    let authorized = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(|t| t == token.as_str())
+        .unwrap_or(false);
+    if authorized {
+        next.run(req).await
+    } else {
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+// Serve the admin API until the process stops
+pub async fn serve(config: AdminConfig, lichess: Arc<Lichess>) {
+    let app = Router::new()
+        .route("/reports", get(reports))
+        .route("/arenas", get(arenas))
+        .route("/status", get(status))
+        .route("/scan/arena/:id", post(scan_arena))
+        .route("/scan/user/:id", post(scan_user))
+        .route("/pause", post(pause))
+        .route("/resume", post(resume))
+        .with_state(lichess)
+        .layer(middleware::from_fn_with_state(
+            Arc::new(config.token.clone()),
+            auth,
+        ));
+    match tokio::net::TcpListener::bind(config.addr).await {
+        Ok(listener) => {
+            info!("Serving admin API on http://{}", config.addr);
+            if let Err(err) = axum::serve(listener, app).await {
+                error!("Admin server error: {err}")
+            }
+        }
+        Err(err) => error!("Could not bind admin server to {}: {err}", config.addr),
+    }
+}
@@ -0,0 +1,177 @@
+use chrono::{serde::ts_milliseconds, DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::lichess::Player;
+
+#[derive(Deserialize, Debug)]
+pub struct LightUser {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GamePlayer {
+    // `None` for anonymous players
+    pub user: Option<LightUser>,
+    pub rating: Option<u16>,
+    #[serde(default)]
+    pub berserk: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GamePlayers {
+    pub white: GamePlayer,
+    pub black: GamePlayer,
+}
+
+// One line of `/api/tournament/{id}/games` as ndjson
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ArenaGame {
+    pub players: GamePlayers,
+    // `white` or `black`, `None` for a draw
+    pub winner: Option<String>,
+    #[serde(with = "ts_milliseconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds")]
+    pub last_move_at: DateTime<Utc>,
+}
+
+// How the player played in the arena itself
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ArenaStats {
+    pub games: usize,
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+    pub berserk_percent: u8,
+    pub longest_win_streak: usize,
+    pub avg_opponent_rating: Option<u16>,
+    pub max_opponent_rating: Option<u16>,
+    pub avg_duration_secs: Option<i64>,
+    // performance computed on these games, and its difference with the player rating
+    pub performance: Option<u16>,
+    pub performance_gap: Option<i32>,
+}
+
+impl ArenaStats {
+    // `games` can also contain games of other players, they are ignored
+    pub fn new(player: &Player, games: &[ArenaGame]) -> Self {
+        let user_id = player.username.to_lowercase();
+        let mut games: Vec<(&ArenaGame, bool)> = games
+            .iter()
+            .filter_map(|g| {
+                let is_player = |p: &GamePlayer| {
+                    p.user
+                        .as_ref()
+                        .map(|u| u.name.to_lowercase() == user_id)
+                        .unwrap_or(false)
+                };
+                if is_player(&g.players.white) {
+                    Some((g, true))
+                } else if is_player(&g.players.black) {
+                    Some((g, false))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        games.sort_by_key(|(g, _)| g.created_at);
+        let mut stats = Self {
+            games: games.len(),
+            ..Default::default()
+        };
+        if games.is_empty() {
+            return stats;
+        }
+        let (mut streak, mut berserks) = (0, 0);
+        let mut opponent_ratings = vec![];
+        for (game, is_white) in &games {
+            let (me, opponent) = if *is_white {
+                (&game.players.white, &game.players.black)
+            } else {
+                (&game.players.black, &game.players.white)
+            };
+            match game.winner.as_deref() {
+                None => {
+                    stats.draws += 1;
+                    streak = 0
+                }
+                Some(winner) if (winner == "white") == *is_white => {
+                    stats.wins += 1;
+                    streak += 1;
+                    stats.longest_win_streak = stats.longest_win_streak.max(streak)
+                }
+                Some(_) => {
+                    stats.losses += 1;
+                    streak = 0
+                }
+            }
+            if me.berserk {
+                berserks += 1
+            }
+            opponent_ratings.extend(opponent.rating);
+        }
+        stats.berserk_percent = (berserks * 100 / games.len()) as u8;
+        if !opponent_ratings.is_empty() {
+            let avg = opponent_ratings.iter().map(|r| u32::from(*r)).sum::<u32>()
+                / opponent_ratings.len() as u32;
+            stats.avg_opponent_rating = Some(avg as u16);
+            stats.max_opponent_rating = opponent_ratings.iter().max().copied();
+            // usual linear approximation of a performance rating
+            let n = games.len() as i32;
+            let performance = avg as i32 + 500 * (stats.wins as i32 - stats.losses as i32) / n;
+            stats.performance = u16::try_from(performance).ok();
+            stats.performance_gap = Some(performance - i32::from(player.rating));
+        }
+        stats.avg_duration_secs = Some(
+            games
+                .iter()
+                .map(|(g, _)| (g.last_move_at - g.created_at).num_seconds())
+                .sum::<i64>()
+                / games.len() as i64,
+        );
+        stats
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GAMES: &str = r#"{"id":"aaaaaaaa","players":{"white":{"user":{"name":"German11"},"rating":1300,"berserk":true},"black":{"user":{"name":"foo"},"rating":1400}},"winner":"white","createdAt":1700000000000,"lastMoveAt":1700000060000}
+{"id":"bbbbbbbb","players":{"white":{"user":{"name":"bar"},"rating":1500},"black":{"user":{"name":"German11"},"rating":1310,"berserk":true}},"winner":"black","createdAt":1700000100000,"lastMoveAt":1700000200000}
+{"id":"cccccccc","players":{"white":{"user":{"name":"German11"},"rating":1320},"black":{"user":{"name":"baz"},"rating":1600}},"createdAt":1700000300000,"lastMoveAt":1700000420000}"#;
+
+    #[test]
+    fn test_arena_stats() {
+        let games: Vec<ArenaGame> = GAMES
+            .lines()
+            .map(|l| serde_json::from_str(l).expect("valid game"))
+            .collect();
+        let player = Player {
+            rank: 1,
+            score: 10,
+            rating: 1300,
+            username: "german11".to_string(),
+            performance: None,
+        };
+        let stats = ArenaStats::new(&player, &games);
+        assert_eq!(
+            stats,
+            ArenaStats {
+                games: 3,
+                wins: 2,
+                draws: 1,
+                losses: 0,
+                berserk_percent: 66,
+                longest_win_streak: 2,
+                avg_opponent_rating: Some(1500),
+                max_opponent_rating: Some(1600),
+                avg_duration_secs: Some(93),
+                performance: Some(1833),
+                performance_gap: Some(533),
+            }
+        );
+    }
+}
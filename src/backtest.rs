@@ -0,0 +1,90 @@
+// Replay the screening on arenas saved to disk, without any request
+//
+// Expected layout of the directory:
+// - `arenas.json`: response of `/api/tournament`, or a list of `/api/tournament/{arena_id}`
+//   responses, the only ones with the entry conditions
+// - `{arena_id}.ndjson`: response of `/api/tournament/{arena_id}/results`
+// - `games/{user_id}.{perf}.pgn`: response of `/api/games/user/{user_id}?perfType={perf}`,
+//   only the games in the `lookback` window of the arena are used
+// - `users.json`: response of `/api/users`, optional
+use std::{collections::HashMap, fs, io, path::Path};
+
+use serde::Deserialize;
+use serde_json::json;
+use tracing::warn;
+
+use crate::{
+    error::Error,
+    game_visitor::get_games,
+    lichess::{Arena, Arenas, Lichess, Player, User},
+    lookback::GameWindow,
+};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ArenasFile {
+    Arenas(Arenas),
+    List(Vec<Arena>),
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> io::Result<T> {
+    serde_json::from_str(&fs::read_to_string(path)?).map_err(io::Error::other)
+}
+
+// Print the decision taken for each preselected player as JSON lines, returns the number of reports
+pub fn backtest(lichess: &Lichess, dir: &Path) -> io::Result<usize> {
+    let arenas = match read_json::<ArenasFile>(&dir.join("arenas.json"))? {
+        ArenasFile::Arenas(arenas) => arenas.finished,
+        ArenasFile::List(arenas) => arenas,
+    };
+    let users: HashMap<String, User> = read_json::<Vec<User>>(&dir.join("users.json"))
+        .map(|users| users.into_iter().map(|u| (u.id.clone(), u)).collect())
+        .unwrap_or_default();
+    let mut reports = 0;
+    let short_loss = lichess.short_loss();
+    for arena in arenas.iter().filter(|a| a.has_max_rating) {
+        // the same games as a live screening would fetch
+        let window = GameWindow::for_arena(arena, lichess.lookback().speed(&arena.schedule.speed));
+        let results = match fs::read_to_string(dir.join(format!("{}.ndjson", arena.id))) {
+            Ok(results) => results,
+            Err(err) => {
+                warn!("Skipping arena {}: {err}", arena.id);
+                continue;
+            }
+        };
+        for player in results
+            .lines()
+            .filter(|l| !l.is_empty())
+            .filter_map(|l| serde_json::from_str::<Player>(l).ok())
+            .filter(|p| lichess.preselect_player(arena, p))
+        {
+            let user_id = player.username.to_lowercase();
+            let games = fs::read_to_string(
+                dir.join("games")
+                    .join(format!("{user_id}.{}.pgn", arena.perf.key)),
+            )
+            .map_err(Error::from)
+            .and_then(|pgn| get_games(pgn, &player.username))
+            .map(|counter| counter.games)
+            .unwrap_or_default();
+            let sus_games = short_loss.sus_games(&window.filter(games));
+            let (severity, rules) =
+                lichess.evaluate(arena, &player, users.get(&user_id), &sus_games);
+            if !rules.is_empty() {
+                reports += 1
+            }
+            println!(
+                "{}",
+                json!({
+                    "arena": arena.id,
+                    "user": player.username,
+                    "score": player.score,
+                    "reported": !rules.is_empty(),
+                    "severity": severity,
+                    "rules": rules,
+                })
+            );
+        }
+    }
+    Ok(reports)
+}
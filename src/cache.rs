@@ -0,0 +1,181 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use crate::{
+    game_visitor::GameResult,
+    lichess::User,
+    util::{load_json, save_json},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationSeconds};
+
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct CacheConfig {
+    // time in seconds before refetching a user info
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub users_ttl: Duration,
+    // time in seconds before fetching the games played since the last export
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub games_ttl: Duration,
+    // where to persist the cache across restarts
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Cached<T> {
+    fetched_at: DateTime<Utc>,
+    value: T,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct Entries {
+    users: HashMap<String, Cached<User>>,
+    // by `games_key`
+    games: HashMap<String, Cached<Vec<GameResult>>>,
+}
+
+pub enum Lookup<T> {
+    Fresh(T),
+    // expired, only what happened since the last fetch is needed
+    Stale(DateTime<Utc>),
+    Missing,
+}
+
+// The window is a length, not a date, so the key does not change every day
+pub fn games_key(user_id: &str, perf: &str, window: chrono::Duration) -> String {
+    format!("{}:{perf}:{}d", user_id.to_lowercase(), window.num_days())
+}
+
+// User info and game exports, players are met in many arenas a day
+#[derive(Debug)]
+pub struct Cache {
+    config: CacheConfig,
+    entries: Entries,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Self {
+        let entries = load_json(config.path.as_deref()).unwrap_or_default();
+        Self { config, entries }
+    }
+
+    fn is_fresh<T>(cached: &Cached<T>, ttl: Duration) -> bool {
+        chrono::Duration::from_std(ttl)
+            .map(|ttl| cached.fetched_at + ttl > Utc::now())
+            .unwrap_or(true)
+    }
+
+    pub fn user(&self, user_id: &str) -> Option<User> {
+        self.entries
+            .users
+            .get(user_id)
+            .filter(|c| Self::is_fresh(c, self.config.users_ttl))
+            .map(|c| c.value.clone())
+    }
+
+    pub fn insert_user(&mut self, user: User) {
+        self.entries.users.insert(
+            user.id.clone(),
+            Cached {
+                fetched_at: Utc::now(),
+                value: user,
+            },
+        );
+    }
+
+    pub fn games(&self, key: &str) -> Lookup<Vec<GameResult>> {
+        match self.entries.games.get(key) {
+            Some(c) if Self::is_fresh(c, self.config.games_ttl) => Lookup::Fresh(c.value.clone()),
+            Some(c) => Lookup::Stale(c.fetched_at),
+            None => Lookup::Missing,
+        }
+    }
+
+    // `new_games` are the most recent ones, the oldest are dropped beyond `max` or before `since`
+    pub fn merge_games(
+        &mut self,
+        key: &str,
+        new_games: Vec<GameResult>,
+        since: DateTime<Utc>,
+        max: usize,
+    ) -> Vec<GameResult> {
+        let old_games = self
+            .entries
+            .games
+            .remove(key)
+            .map(|c| c.value)
+            .unwrap_or_default();
+        let mut games = new_games;
+        for game in old_games {
+            if !games.iter().any(|g| g.id == game.id) {
+                games.push(game)
+            }
+        }
+        games.retain(|g| g.played_at().map(|t| t >= since).unwrap_or(false));
+        games.truncate(max);
+        self.entries.games.insert(
+            key.to_string(),
+            Cached {
+                fetched_at: Utc::now(),
+                value: games.clone(),
+            },
+        );
+        games
+    }
+
+    // Drop users that expired and games not fetched for a day, then persist if asked to
+    pub fn save(&mut self) {
+        let users_ttl = self.config.users_ttl;
+        self.entries
+            .users
+            .retain(|_, c| Self::is_fresh(c, users_ttl));
+        let day = Duration::from_secs(24 * 3600).max(self.config.games_ttl);
+        self.entries.games.retain(|_, c| Self::is_fresh(c, day));
+        save_json(self.config.path.as_deref(), &self.entries)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn game(id: &str, date: &str) -> GameResult {
+        GameResult {
+            id: id.to_string(),
+            date: date.to_string(),
+            ..GameResult::sample()
+        }
+    }
+
+    #[test]
+    fn test_merge_games() {
+        let mut cache = Cache::new(CacheConfig {
+            users_ttl: Duration::from_secs(60),
+            games_ttl: Duration::from_secs(60),
+            path: None,
+        });
+        let key = games_key("German11", "blitz", chrono::Duration::days(180));
+        assert!(matches!(cache.games(&key), Lookup::Missing));
+        cache.merge_games(
+            &key,
+            vec![game("b", "2024.01.02"), game("a", "2023.01.01")],
+            "2023-06-01T00:00:00Z".parse().unwrap(),
+            3,
+        );
+        let games = cache.merge_games(
+            &key,
+            vec![
+                game("d", "2024.01.04"),
+                game("c", "2024.01.03"),
+                game("b", "2024.01.02"),
+            ],
+            "2023-06-01T00:00:00Z".parse().unwrap(),
+            3,
+        );
+        let ids: Vec<&str> = games.iter().map(|g| g.id.as_str()).collect();
+        assert_eq!(ids, ["d", "c", "b"]);
+        assert!(matches!(cache.games(&key), Lookup::Fresh(g) if g.len() == 3));
+    }
+}
@@ -0,0 +1,70 @@
+// Commands moderators can send to the bot, by mentioning it or in a private message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Check { user_id: String, perf: String },
+    // precision of each rule according to moderators' verdicts
+    Stats,
+    Help,
+}
+
+pub const USAGE: &str =
+    "Usage: `check <user> <perf>`, eg: `check german11 blitz`, or `stats` for rules precision";
+
+impl Command {
+    pub fn parse(content: &str) -> Option<Self> {
+        let content = content.trim();
+        // drop the leading `@**sandbag-bot**` mention, if any
+        let content = content
+            .strip_prefix("@**")
+            .and_then(|s| s.split_once("**"))
+            .map(|(_, rest)| rest)
+            .unwrap_or(content);
+        let mut words = content.split_whitespace();
+        match words.next().map(str::to_lowercase).as_deref() {
+            Some("check") => match (words.next(), words.next()) {
+                (Some(user_id), Some(perf)) => Some(Command::Check {
+                    user_id: user_id.trim_start_matches('@').to_lowercase(),
+                    perf: perf.to_lowercase(),
+                }),
+                _ => Some(Command::Help),
+            },
+            Some("stats") => Some(Command::Stats),
+            Some("help") | None => Some(Command::Help),
+            Some(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_check() {
+        assert_eq!(
+            Command::parse("@**sandbag-bot** check German11 Blitz"),
+            Some(Command::Check {
+                user_id: "german11".to_string(),
+                perf: "blitz".to_string()
+            })
+        );
+        assert_eq!(
+            Command::parse("check german11 bullet"),
+            Some(Command::Check {
+                user_id: "german11".to_string(),
+                perf: "bullet".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_help() {
+        assert_eq!(Command::parse("@**sandbag-bot**"), Some(Command::Help));
+        assert_eq!(Command::parse("check german11"), Some(Command::Help));
+        assert_eq!(Command::parse("hello there"), None);
+        assert_eq!(
+            Command::parse("@**sandbag-bot** stats"),
+            Some(Command::Stats)
+        );
+    }
+}
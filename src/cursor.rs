@@ -0,0 +1,114 @@
+use std::{collections::HashSet, fs, io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::util::{load_json, save_json};
+
+// Position reached in the current cycle, to resume from after a restart
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub arena_id: String,
+    // last rank processed in that arena
+    pub rank: u16,
+}
+
+// Indices of the arenas of the cycle, with the rank after which to screen each of them.
+// Resumes from the cursor if its arena is still listed, starts from scratch otherwise
+pub fn arenas_to_screen(cursor: Option<&Cursor>, arena_ids: &[&str]) -> Vec<(usize, u16)> {
+    let resume = cursor.and_then(|c| {
+        arena_ids
+            .iter()
+            .position(|id| *id == c.arena_id)
+            .map(|i| (i, c.rank))
+    });
+    if let Some((i, rank)) = resume {
+        info!("Resuming from arena {} after rank {rank}", arena_ids[i]);
+    }
+    let (start, rank) = resume.unwrap_or((0, 0));
+    (start..arena_ids.len())
+        .map(|i| (i, if i == start { rank } else { 0 }))
+        .collect()
+}
+
+pub struct CursorStore {
+    path: Option<PathBuf>,
+}
+
+impl CursorStore {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self { path }
+    }
+
+    pub fn load(&self) -> Option<Cursor> {
+        load_json(self.path.as_deref())
+    }
+
+    pub fn save(&self, cursor: &Cursor) {
+        save_json(self.path.as_deref(), cursor)
+    }
+
+    // (player id, arena id) already reported, kept next to the cursor
+    pub fn load_reported(&self) -> HashSet<(String, String)> {
+        load_json(self.reported_path().as_deref()).unwrap_or_default()
+    }
+
+    pub fn save_reported(&self, reported: &HashSet<(String, String)>) {
+        save_json(self.reported_path().as_deref(), reported)
+    }
+
+    fn reported_path(&self) -> Option<PathBuf> {
+        self.path
+            .as_ref()
+            .map(|p| p.with_extension("reported.json"))
+    }
+
+    // the cycle went through, next one starts from scratch
+    pub fn clear(&self) {
+        if let Some(path) = &self.path {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                    warn!("Could not remove cursor {path:?}: {err}")
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_arenas_to_screen() {
+        let ids = ["a", "b", "c"];
+        let cursor = Cursor {
+            arena_id: "b".to_string(),
+            rank: 42,
+        };
+        assert_eq!(arenas_to_screen(Some(&cursor), &ids), [(1, 42), (2, 0)]);
+        // the arena left the list since the shutdown
+        let cursor = Cursor {
+            arena_id: "z".to_string(),
+            rank: 42,
+        };
+        assert_eq!(
+            arenas_to_screen(Some(&cursor), &ids),
+            [(0, 0), (1, 0), (2, 0)]
+        );
+        assert_eq!(arenas_to_screen(None, &ids), [(0, 0), (1, 0), (2, 0)]);
+    }
+
+    #[test]
+    fn test_reported_next_to_cursor() {
+        let path = std::env::temp_dir().join(format!("sandbag-cursor-{}.json", std::process::id()));
+        let store = CursorStore::new(Some(path.clone()));
+        let reported = HashSet::from([("someone".to_string(), "abcdefgh".to_string())]);
+        store.save_reported(&reported);
+        store.clear();
+        assert_eq!(store.load_reported(), reported);
+        fs::remove_file(path.with_extension("reported.json")).unwrap();
+        assert!(CursorStore::new(None).load_reported().is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+use std::{path::PathBuf, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationSeconds};
+
+use crate::{
+    rule::Rule,
+    score::Severity,
+    util::{load_json, save_json},
+};
+
+#[serde_as]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct DigestConfig {
+    // time in seconds between two digests
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub interval: Duration,
+    // reports of at least this severity are still posted right away
+    pub immediate_severity: Severity,
+    // where to persist pending entries
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DigestEntry {
+    pub user_id: String,
+    pub rating: u16,
+    pub score: u16,
+    pub arena_id: String,
+    pub arena_name: String,
+    pub severity: Severity,
+    pub rules: Vec<Rule>,
+    pub losses: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Pending {
+    since: DateTime<Utc>,
+    entries: Vec<DigestEntry>,
+}
+
+// Findings below `immediate_severity` accumulated between two digests
+#[derive(Debug)]
+pub struct Digest {
+    config: DigestConfig,
+    pending: Pending,
+}
+
+impl Digest {
+    pub fn new(config: DigestConfig) -> Self {
+        let pending = load_json(config.path.as_deref()).unwrap_or_else(|| Pending {
+            since: Utc::now(),
+            entries: vec![],
+        });
+        Self { config, pending }
+    }
+
+    fn save(&self) {
+        save_json(self.config.path.as_deref(), &self.pending)
+    }
+
+    // `path` is only read at startup
+    pub fn reload(&mut self, config: DigestConfig) -> Option<String> {
+        let old = &self.config;
+        if (old.interval, old.immediate_severity) == (config.interval, config.immediate_severity) {
+            return None;
+        }
+        let change = format!(
+            "digest: every {:?} below {} severity -> every {:?} below {}",
+            old.interval, old.immediate_severity, config.interval, config.immediate_severity
+        );
+        self.config = DigestConfig {
+            path: self.config.path.take(),
+            ..config
+        };
+        Some(change)
+    }
+
+    pub fn is_immediate(&self, severity: Severity) -> bool {
+        severity >= self.config.immediate_severity
+    }
+
+    // One entry per player and arena, replaced if the arena is screened again
+    pub fn push(&mut self, entry: DigestEntry) {
+        let entries = &mut self.pending.entries;
+        match entries.iter_mut().find(|e| {
+            e.user_id.eq_ignore_ascii_case(&entry.user_id) && e.arena_id == entry.arena_id
+        }) {
+            Some(e) => *e = entry,
+            None => entries.push(entry),
+        }
+        self.save()
+    }
+
+    fn is_due(&self, now: DateTime<Utc>) -> bool {
+        chrono::Duration::from_std(self.config.interval)
+            .map(|interval| self.pending.since + interval <= now)
+            .unwrap_or(false)
+    }
+
+    // Markdown table of the pending entries if the interval elapsed, resetting them
+    pub fn take_if_due(&mut self, max_len: usize) -> Option<String> {
+        let now = Utc::now();
+        if !self.is_due(now) {
+            return None;
+        }
+        let entries = std::mem::take(&mut self.pending.entries);
+        let since = self.pending.since;
+        self.pending.since = now;
+        self.save();
+        (!entries.is_empty())
+            .then(|| render(&entries, since, self.config.immediate_severity, max_len))
+    }
+}
+
+// `immediate_severity` and above were posted right away
+fn render(
+    entries: &[DigestEntry],
+    since: DateTime<Utc>,
+    immediate_severity: Severity,
+    max_len: usize,
+) -> String {
+    let mut table = format!(
+        "**Digest**: {} findings below {immediate_severity} severity since {}\n| player | score | arena | rules | losses |\n|---|---|---|---|---|\n",
+        entries.len(),
+        since.format("%Y-%m-%d %H:%M UTC")
+    );
+    for (i, e) in entries.iter().enumerate() {
+        let rules = e
+            .rules
+            .iter()
+            .map(Rule::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let row = format!(
+            "| [{0} ({1})](https://lichess.org/@/{0}) | {2} | [{3}](https://lichess.org/tournament/{4}) | {rules} | {5} |\n",
+            e.user_id, e.rating, e.score, e.arena_name, e.arena_id, e.losses
+        );
+        // keep room for the trailing note
+        if table.len() + row.len() + 50 > max_len {
+            table.push_str(&format!("...and {} more", entries.len() - i));
+            break;
+        }
+        table.push_str(&row);
+    }
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(user_id: &str) -> DigestEntry {
+        DigestEntry {
+            user_id: user_id.to_string(),
+            rating: 1300,
+            score: 30,
+            arena_id: "xxxxxxxx".to_string(),
+            arena_name: "≤1500 Blitz Arena".to_string(),
+            severity: Severity::Low,
+            rules: vec![Rule::ManyLosses],
+            losses: 28,
+        }
+    }
+
+    #[test]
+    fn test_render_truncated() {
+        let entries: Vec<DigestEntry> = (0..100).map(|i| entry(&format!("user{i}"))).collect();
+        let table = render(&entries, Utc::now(), Severity::Medium, 2000);
+        assert!(table.starts_with("**Digest**: 100 findings below medium severity"));
+        assert!(table.len() <= 2000);
+        assert!(table.ends_with("more"));
+        assert!(table.contains("user0"));
+    }
+
+    #[test]
+    fn test_push_once_per_arena() {
+        let mut digest = Digest::new(DigestConfig {
+            interval: Duration::from_secs(3600),
+            immediate_severity: Severity::Medium,
+            path: None,
+        });
+        digest.push(entry("German11"));
+        digest.push(DigestEntry {
+            losses: 29,
+            ..entry("german11")
+        });
+        digest.push(entry("user1"));
+        assert_eq!(digest.pending.entries.len(), 2);
+        assert_eq!(digest.pending.entries[0].losses, 29);
+    }
+}
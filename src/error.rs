@@ -0,0 +1,70 @@
+use std::{fmt, io};
+
+// What can go wrong while talking to lichess or reading its answers
+#[derive(Debug)]
+pub enum Error {
+    Http(reqwest::Error),
+    // reading a streamed body
+    Io(io::Error),
+    Json(serde_json::Error),
+    Pgn(io::Error),
+    Timeout,
+}
+
+impl Error {
+    // of a request that failed with an error status
+    pub fn status(&self) -> Option<reqwest::StatusCode> {
+        match self {
+            Error::Http(err) => err.status(),
+            _ => None,
+        }
+    }
+
+    // eg: the games of a user who does not exist
+    pub fn is_not_found(&self) -> bool {
+        self.status() == Some(reqwest::StatusCode::NOT_FOUND)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(err) => write!(f, "http error: {err}"),
+            Error::Io(err) => write!(f, "io error: {err}"),
+            Error::Json(err) => write!(f, "invalid json: {err}"),
+            Error::Pgn(err) => write!(f, "invalid pgn: {err}"),
+            Error::Timeout => write!(f, "request timed out"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(err) => Some(err),
+            Error::Io(err) | Error::Pgn(err) => Some(err),
+            Error::Json(err) => Some(err),
+            Error::Timeout => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -1,15 +1,68 @@
-use pgn_reader::{BufferedReader, RawHeader, SanPlus, Skip, Visitor};
+use std::str::FromStr;
 
-use crate::util::log_and_pass;
+use chrono::{DateTime, NaiveDateTime, TimeZone as _, Utc};
+use pgn_reader::{BufferedReader, RawComment, RawHeader, SanPlus, Skip, Visitor};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    short_loss::{ShortLossConfig, SusGames},
+    util::log_and_pass,
+};
 
 type GameId = String;
 
-#[derive(Debug, Hash, Clone)]
+#[derive(Debug, Hash, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct GameResult {
     pub id: String,
     pub moves: usize,
     pub won: bool,
     pub is_white: bool,
+    pub opponent: String,
+    // of the player, before the game
+    #[serde(default)]
+    pub rating: Option<u16>,
+    pub opponent_rating: Option<u16>,
+    // eg: `Normal`, `Time forfeit`, `Abandoned`
+    pub termination: String,
+    // clock of the user after their last move, eg: `0:00:42`
+    pub clock_left: Option<String>,
+    pub rating_diff: Option<i16>,
+    // `YYYY.MM.DD`
+    pub date: String,
+    // `HH:MM:SS`, UTC
+    #[serde(default)]
+    pub time: String,
+}
+
+impl GameResult {
+    pub fn played_at(&self) -> Option<DateTime<Utc>> {
+        NaiveDateTime::parse_from_str(&format!("{} {}", self.date, self.time), "%Y.%m.%d %H:%M:%S")
+            .ok()
+            .map(|dt| Utc.from_utc_datetime(&dt))
+    }
+}
+
+#[cfg(test)]
+impl GameResult {
+    // A loss as white, tests override what they look at
+    pub fn sample() -> Self {
+        Self {
+            id: "xxxxxxxx".to_string(),
+            moves: 10,
+            won: false,
+            is_white: true,
+            opponent: "someone".to_string(),
+            rating: None,
+            opponent_rating: None,
+            termination: "Normal".to_string(),
+            clock_left: None,
+            rating_diff: None,
+            date: "2022.03.04".to_string(),
+            time: "10:00:00".to_string(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -18,6 +71,16 @@ struct TempGame {
     pub counter: usize,
     pub won: Option<bool>,
     pub is_white: Option<bool>,
+    pub white: Option<String>,
+    pub black: Option<String>,
+    pub white_elo: Option<u16>,
+    pub black_elo: Option<u16>,
+    pub white_rating_diff: Option<i16>,
+    pub black_rating_diff: Option<i16>,
+    pub termination: Option<String>,
+    pub date: Option<String>,
+    pub time: Option<String>,
+    pub clock_left: Option<String>,
 }
 
 #[derive(Debug, Hash, Copy, Clone)]
@@ -26,12 +89,36 @@ struct TempGameError;
 impl TryInto<GameResult> for TempGame {
     type Error = TempGameError;
 
-    fn try_into(self) -> Result<GameResult, Self::Error> {
+    fn try_into(self) -> std::result::Result<GameResult, Self::Error> {
+        let is_white = self.is_white.ok_or(TempGameError)?;
+        let (opponent, rating, opponent_rating, rating_diff) = if is_white {
+            (
+                self.black,
+                self.white_elo,
+                self.black_elo,
+                self.white_rating_diff,
+            )
+        } else {
+            (
+                self.white,
+                self.black_elo,
+                self.white_elo,
+                self.black_rating_diff,
+            )
+        };
         Ok(GameResult {
             id: self.id.ok_or(TempGameError)?,
             moves: self.counter,
             won: self.won.ok_or(TempGameError)?,
-            is_white: self.is_white.ok_or(TempGameError)?,
+            is_white,
+            opponent: opponent.unwrap_or_default(),
+            rating,
+            opponent_rating,
+            termination: self.termination.unwrap_or_default(),
+            clock_left: self.clock_left,
+            rating_diff,
+            date: self.date.unwrap_or_default(),
+            time: self.time.unwrap_or_default(),
         })
     }
 }
@@ -52,11 +139,8 @@ impl MoveCounter {
         }
     }
 
-    pub fn get_sorted_sus_games(&self) -> Vec<GameResult> {
-        let mut sus_games: Vec<GameResult> =
-            self.games.clone().into_iter().filter(|g| !g.won).collect();
-        sus_games.sort_by(|a, b| a.moves.cmp(&b.moves));
-        sus_games
+    pub fn get_sorted_sus_games(&self, short_loss: &ShortLossConfig) -> SusGames {
+        short_loss.sus_games(&self.games)
     }
 }
 
@@ -74,7 +158,25 @@ impl Visitor for MoveCounter {
                 self.temp.id =
                     value_opt.and_then(|s| s.split('/').next_back().map(|s| s.to_string()))
             }
-            b"White" => self.temp.is_white = value_opt.map(|s| s.contains(&self.user_id)),
+            // ids are lowercase, headers keep the username's case
+            b"White" => {
+                self.temp.is_white = value_opt
+                    .as_ref()
+                    .map(|s| s.eq_ignore_ascii_case(&self.user_id));
+                self.temp.white = value_opt.map(|s| s.to_string())
+            }
+            b"Black" => self.temp.black = value_opt.map(|s| s.to_string()),
+            b"WhiteElo" => self.temp.white_elo = value_opt.and_then(|s| u16::from_str(&s).ok()),
+            b"BlackElo" => self.temp.black_elo = value_opt.and_then(|s| u16::from_str(&s).ok()),
+            b"WhiteRatingDiff" => {
+                self.temp.white_rating_diff = value_opt.and_then(|s| i16::from_str(&s).ok())
+            }
+            b"BlackRatingDiff" => {
+                self.temp.black_rating_diff = value_opt.and_then(|s| i16::from_str(&s).ok())
+            }
+            b"Termination" => self.temp.termination = value_opt.map(|s| s.to_string()),
+            b"UTCDate" => self.temp.date = value_opt.map(|s| s.to_string()),
+            b"UTCTime" => self.temp.time = value_opt.map(|s| s.to_string()),
             b"Result" => {
                 self.temp.won = value_opt.zip(self.temp.is_white).map(|(v, is_white)| {
                     if is_white {
@@ -92,6 +194,24 @@ impl Visitor for MoveCounter {
         self.temp.counter += 1;
     }
 
+    // `{ [%clk 0:00:42] }`, the comment follows the move
+    fn comment(&mut self, comment: RawComment<'_>) {
+        let user_moved = self
+            .temp
+            .is_white
+            .map(|is_white| is_white == (self.temp.counter % 2 == 1))
+            .unwrap_or(false);
+        if user_moved {
+            if let Some(clock) = String::from_utf8_lossy(comment.as_bytes())
+                .split("[%clk ")
+                .nth(1)
+                .and_then(|s| s.split(']').next())
+            {
+                self.temp.clock_left = Some(clock.trim().to_string())
+            }
+        }
+    }
+
     fn begin_variation(&mut self) -> Skip {
         Skip(true) // stay in the mainline
     }
@@ -103,10 +223,61 @@ impl Visitor for MoveCounter {
     }
 }
 
-pub fn get_games(games: String, user_id: &str) -> MoveCounter {
+pub fn get_games(games: String, user_id: &str) -> Result<MoveCounter> {
     let mut reader = BufferedReader::new_cursor(&games[..]);
 
     let mut counter = MoveCounter::new(user_id.to_string());
-    reader.read_all(&mut counter).expect("valid pgn");
-    counter
+    reader.read_all(&mut counter).map_err(Error::Pgn)?;
+    Ok(counter)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PGN: &str = r#"[Event "Rated Blitz game"]
+[Site "https://lichess.org/abcdefgh"]
+[Date "2022.03.04"]
+[White "german11"]
+[Black "opponent"]
+[Result "0-1"]
+[UTCDate "2022.03.04"]
+[UTCTime "10:00:00"]
+[WhiteElo "1400"]
+[BlackElo "1450"]
+[WhiteRatingDiff "-5"]
+[BlackRatingDiff "+5"]
+[Termination "Normal"]
+
+1. f3 { [%clk 0:03:00] } 1... e5 { [%clk 0:03:00] } 2. g4 { [%clk 0:02:58] } 2... Qh4# { [%clk 0:02:59] } 0-1
+
+"#;
+
+    #[test]
+    fn test_get_games() {
+        let counter = get_games(PGN.to_string(), "german11").expect("valid pgn");
+        let g = &counter.games[0];
+        assert_eq!(g.id, "abcdefgh");
+        assert_eq!(g.moves, 4);
+        assert!(!g.won);
+        assert!(g.is_white);
+        assert_eq!(g.opponent, "opponent");
+        assert_eq!(g.rating, Some(1400));
+        assert_eq!(g.opponent_rating, Some(1450));
+        assert_eq!(g.rating_diff, Some(-5));
+        assert_eq!(g.termination, "Normal");
+        assert_eq!(g.clock_left.as_deref(), Some("0:02:58"));
+        assert_eq!(g.date, "2022.03.04");
+        assert_eq!(g.played_at(), Some("2022-03-04T10:00:00Z".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_get_games_color() {
+        let pgn = PGN.replace("german11", "German11");
+        let counter = get_games(pgn.clone(), "german11").expect("valid pgn");
+        assert!(counter.games[0].is_white);
+        // not a prefix match
+        let counter = get_games(pgn, "german1").expect("valid pgn");
+        assert!(!counter.games[0].is_white);
+    }
 }
@@ -1,80 +1,261 @@
-use std::{collections::HashMap, io, str::FromStr, time::Duration};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
-use chrono::{serde::ts_milliseconds, DateTime, Utc};
+use chrono::{serde::ts_milliseconds, DateTime, TimeZone as _, Utc};
 use futures_util::stream::{Stream, StreamExt as _, TryStreamExt as _};
-use log::{debug, info, warn};
-use reqwest::{Error, IntoUrl, Response};
-use serde::Deserialize;
-use tokio::{io::AsyncBufReadExt as _, time::timeout};
+use reqwest::{header::ACCEPT, IntoUrl, RequestBuilder, Response};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use tokio::{
+    io::AsyncBufReadExt as _,
+    sync::{mpsc::UnboundedReceiver, Notify},
+    time::{sleep, timeout},
+};
 use tokio_stream::wrappers::LinesStream;
 use tokio_util::io::StreamReader;
+use tracing::{debug, info, instrument, warn};
 
 use crate::{
-    game_visitor::{get_games, MoveCounter},
-    score::SusScore,
-    util::{log_and_pass, req, Auth},
-    zulip::Zulip,
+    arena_stats::{ArenaGame, ArenaStats},
+    cache::{games_key, Cache, Lookup},
+    command::{Command, USAGE},
+    cursor::{arenas_to_screen, Cursor, CursorStore},
+    digest::{Digest, DigestEntry},
+    error::{Error, Result},
+    game_visitor::{get_games, GameResult, MoveCounter},
+    lookback::{GameWindow, LookbackConfig},
+    loss_cluster::LossClusterConfig,
+    metrics::{
+        ARENAS_SCREENED, CYCLE_DURATION, GAME_EXPORTS, PLAYERS_PRESELECTED, REPORTS, REPORT_RULES,
+    },
+    notifier::{DryRun, Notifier, Webhook},
+    reload::ConfigWatcher,
+    report::Report,
+    rule::Rule,
+    score::{Severity, SusScore},
+    short_loss::{ShortLossConfig, SusGames},
+    status::Status,
+    util::{req, req_once, Auth},
+    zulip::{EventsError, Zulip, MAX_MESSAGE_LEN},
     Settings,
 };
 
+// On-demand lookups fail fast, eg: the games export of a user who does not exist is a 404
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retry {
+    Forever,
+    Never,
+}
+
+// reports kept in memory for the admin API
+const RECENT_REPORTS: usize = 100;
+const GAMES_PAGE_SIZE: usize = 100;
+
 pub struct Lichess {
-    zulip: Zulip,
+    zulip: Arc<Zulip>,
+    // zulip, unless muted, and the other sinks. Only the dry-run one if set
+    notifiers: Vec<Arc<dyn Notifier>>,
+    dry_run: bool,
     token: Option<Auth>,
-    sus_score: SusScore,
+    // reloadable, see `reload_config`
+    sus_score: Mutex<SusScore>,
+    digest: Option<Mutex<Digest>>,
+    // (player id, arena id) already sent to the notifiers
+    reported: Mutex<HashSet<(String, String)>>,
+    status: Mutex<Status>,
+    heartbeat: Mutex<Option<chrono::Duration>>,
+    sleep_time: Mutex<Duration>,
+    recent_reports: Mutex<VecDeque<Report>>,
+    // arenas screened during the last cycle
+    last_arenas: Mutex<Vec<Arena>>,
+    paused: AtomicBool,
+    stopping: AtomicBool,
+    stop: Notify,
+    cursor: CursorStore,
+    cache: Option<Mutex<Cache>>,
+    loss_cluster: Mutex<Option<LossClusterConfig>>,
+    lookback: Mutex<LookbackConfig>,
+    short_loss: Mutex<ShortLossConfig>,
 }
 
 #[derive(Deserialize, Debug, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct Arenas {
-    pub created: Vec<Arena>,
     pub finished: Vec<Arena>,
 }
 
-#[derive(Deserialize, Debug, Default)]
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
 pub struct Perf {
     pub key: String, // TODO use enum instead
 }
 
-#[derive(Deserialize, Debug, Default)]
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
 pub struct Schedule {
     pub freq: String,
     pub speed: String,
 }
 
 // schedule":{"freq":"hourly","speed":"hyperBullet"}
-#[derive(Deserialize, Debug, Default)]
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Arena {
     pub id: String,
     #[serde(default)]
     pub has_max_rating: bool, // if not None, should always be true
+    #[serde(default)] // only scheduled arenas have one
     pub schedule: Schedule,
     pub perf: Perf,
     pub full_name: String,
+    // a timestamp in the list, a date in `/api/tournament/{id}`
+    #[serde(default, deserialize_with = "timestamp_or_date")]
+    pub starts_at: Option<DateTime<Utc>>,
+    // only in the list
+    #[serde(default, deserialize_with = "timestamp_or_date")]
+    pub finishes_at: Option<DateTime<Utc>>,
+    // duration, only in `/api/tournament/{id}`
+    #[serde(default)]
+    pub minutes: Option<u32>,
+    // only in `/api/tournament/{id}`
+    #[serde(flatten)]
+    pub conditions: Conditions,
+    // winners, only in `/api/tournament/{id}`
+    #[serde(default)]
+    pub podium: Vec<Player>,
+}
+
+fn timestamp_or_date<'de, D: Deserializer<'de>>(
+    d: D,
+) -> std::result::Result<Option<DateTime<Utc>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum TimestampOrDate {
+        Timestamp(i64),
+        Date(DateTime<Utc>),
+    }
+    Ok(match Option::<TimestampOrDate>::deserialize(d)? {
+        Some(TimestampOrDate::Timestamp(ms)) => Utc.timestamp_millis_opt(ms).single(),
+        Some(TimestampOrDate::Date(date)) => Some(date),
+        None => None,
+    })
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct RatingCondition {
+    pub rating: u16,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct RatedGamesCondition {
+    pub nb: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TeamCondition {
+    pub team_id: String,
+}
+
+// Entry requirements of an arena
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Conditions {
+    #[serde(default, deserialize_with = "lenient")]
+    pub max_rating: Option<RatingCondition>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub min_rating: Option<RatingCondition>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub min_rated_games: Option<RatedGamesCondition>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub team_member: Option<TeamCondition>,
+    // in days
+    #[serde(default, deserialize_with = "lenient")]
+    pub min_account_age: Option<u16>,
+}
+
+// A condition of an unexpected shape is dropped instead of failing the whole arena
+fn lenient<'de, D: Deserializer<'de>, T: DeserializeOwned>(
+    d: D,
+) -> std::result::Result<Option<T>, D::Error> {
+    Ok(
+        Option::<serde_json::Value>::deserialize(d)?.and_then(|value| {
+            serde_json::from_value(value.clone())
+                .map_err(|err| warn!("Ignoring arena condition {value}: {err}"))
+                .ok()
+        }),
+    )
+}
+
+// Trimmed `/api/tournament/{id}` response of a finished arena
+#[cfg(test)]
+pub const ARENA_DETAIL: &str = r#"{"nbPlayers":312,"duels":[],"isFinished":true,
+"podium":[{"name":"german11","rank":1,"rating":1290,"score":62,"nb":{"game":30,"berserk":5,"win":22},"performance":2050}],
+"pairingsClosed":true,"standing":{"page":1,"players":[]},"id":"xxxxxxxx","createdBy":"lichess",
+"startsAt":"2022-03-04T12:00:00Z","system":"arena","fullName":"≤1500 Blitz Arena","minutes":57,
+"perf":{"key":"blitz","name":"Blitz","icon":")"},"clock":{"limit":180,"increment":0},"variant":"standard",
+"rated":true,"berserkable":true,"verdicts":{"list":[{"condition":"Rated ≤ 1500 in Blitz for the last week","verdict":"ok"},
+{"condition":"≥ 20 Blitz rated games","verdict":"ok"}],"accepted":true},"schedule":{"freq":"hourly","speed":"blitz"},
+"maxRating":{"perf":"blitz","rating":1500},"minRatedGames":{"perf":"blitz","nb":20}}"#;
+
+impl Conditions {
+    // eg: `rating ≤ 1500, 20+ rated games`
+    pub fn describe(&self) -> Vec<String> {
+        let mut conditions = vec![];
+        if let Some(c) = &self.max_rating {
+            conditions.push(format!("rating ≤ {}", c.rating))
+        }
+        if let Some(c) = &self.min_rating {
+            conditions.push(format!("rating ≥ {}", c.rating))
+        }
+        if let Some(c) = &self.min_rated_games {
+            conditions.push(format!("{}+ rated games", c.nb))
+        }
+        if let Some(c) = &self.team_member {
+            conditions.push(format!("team {}", c.team_id))
+        }
+        if let Some(days) = self.min_account_age {
+            conditions.push(format!("account {days}+ days old"))
+        }
+        conditions
+    }
 }
 
 impl Arena {
     pub fn rating_limit(&self) -> Option<u16> {
-        if self.has_max_rating {
-            // safer version of &self.full_name[1..5]
-            u16::from_str(&self.full_name.chars().skip(1).take(4).collect::<String>()).ok()
-        } else {
-            None
-        }
+        self.conditions.max_rating.as_ref().map(|c| c.rating)
+    }
+
+    // from the list, or the start and duration of `/api/tournament/{id}`
+    pub fn ends_at(&self) -> Option<DateTime<Utc>> {
+        self.finishes_at
+            .or_else(|| Some(self.starts_at? + chrono::Duration::minutes(self.minutes?.into())))
     }
 }
 
 // {"rank":2,"score":57,"rating":2611,"username":"xxx","performance":2462}
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Player {
     pub rank: u16,
     pub score: u16,
     pub rating: u16,
+    // `name` in the arena podium
+    #[serde(alias = "name")]
     pub username: String,
     pub performance: Option<u16>,
 }
 
-#[derive(Deserialize, Debug)]
+impl Player {
+    pub fn is_podium(&self) -> bool {
+        self.rank <= 3
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct User {
     pub id: String,
@@ -82,6 +263,14 @@ pub struct User {
     pub tos_violation: bool,
     #[serde(with = "ts_milliseconds")]
     pub created_at: DateTime<Utc>,
+    // by perf key
+    #[serde(default)]
+    pub perfs: HashMap<String, UserPerf>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct UserPerf {
+    pub games: u32,
 }
 
 impl User {
@@ -89,42 +278,105 @@ impl User {
         self.created_at > (Utc::now() - chrono::Duration::days(20))
     }
 
-    pub fn is_very_new(&self) -> bool {
-        self.created_at > (Utc::now() - chrono::Duration::days(10))
+    // Barely meets the rated games or account age required to join
+    pub fn is_just_eligible(&self, arena: &Arena) -> bool {
+        let conditions = &arena.conditions;
+        let few_games = conditions
+            .min_rated_games
+            .as_ref()
+            .zip(self.perfs.get(&arena.perf.key))
+            .map(|(c, perf)| perf.games < 2 * c.nb)
+            .unwrap_or(false);
+        let young = conditions
+            .min_account_age
+            .map(|days| self.created_at > Utc::now() - chrono::Duration::days(i64::from(days) + 7))
+            .unwrap_or(false);
+        few_games || young
     }
 }
 
+// Replace the setting behind `current` if `new` differs, describing the change
+fn swap<T: PartialEq + fmt::Debug>(name: &str, current: &Mutex<T>, new: T) -> Option<String> {
+    let mut current = current.lock().unwrap();
+    if *current == new {
+        return None;
+    }
+    let change = format!("{name}: {:?} -> {new:?}", *current);
+    *current = new;
+    Some(change)
+}
+
 impl Lichess {
-    pub fn new(settings: Settings) -> Self {
+    // `Err` if a report template is invalid
+    pub fn new(settings: Settings) -> std::result::Result<Self, String> {
         info!("Score threshold used for reporting: {:?}", settings.score);
-        Self {
-            zulip: Zulip::new(settings.zulip.clone()),
+        let zulip = Arc::new(Zulip::new(settings.zulip.clone())?);
+        let dry_run = settings.dry_run.is_some();
+        let notifiers: Vec<Arc<dyn Notifier>> = match settings.dry_run {
+            Some(config) => {
+                info!("Dry run, nothing will be posted");
+                vec![Arc::new(DryRun::new(config)?)]
+            }
+            None => {
+                let mut notifiers: Vec<Arc<dyn Notifier>> = vec![];
+                if !zulip.is_muted() {
+                    notifiers.push(zulip.clone())
+                }
+                for config in settings.sinks {
+                    notifiers.push(Arc::new(Webhook::new(config)?))
+                }
+                notifiers
+            }
+        };
+        let cursor = CursorStore::new(settings.cursor_path.clone());
+        Ok(Self {
+            zulip,
+            notifiers,
+            dry_run,
+            status: Mutex::new(Status::new()),
+            recent_reports: Mutex::new(VecDeque::new()),
+            last_arenas: Mutex::new(vec![]),
+            paused: AtomicBool::new(false),
+            stopping: AtomicBool::new(false),
+            stop: Notify::new(),
+            reported: Mutex::new(cursor.load_reported()),
+            cursor,
+            heartbeat: Mutex::new(
+                settings
+                    .heartbeat
+                    .and_then(|h| chrono::Duration::from_std(h).ok()),
+            ),
+            sleep_time: Mutex::new(settings.sleep_time),
             token: settings.lichess_token.map(Auth::Bearer),
-            sus_score: settings.score,
-        }
-    }
-    async fn post<T: IntoUrl + Copy>(&self, url: T, body: String) -> Response {
-        req(
-            &self.zulip.http,
-            self.zulip.http.post(url).body(body),
-            &self.token,
-        )
-        .await
+            sus_score: Mutex::new(settings.score),
+            digest: settings.digest.map(|c| Mutex::new(Digest::new(c))),
+            cache: settings.cache.map(|c| Mutex::new(Cache::new(c))),
+            loss_cluster: Mutex::new(settings.loss_cluster),
+            lookback: Mutex::new(settings.lookback),
+            short_loss: Mutex::new(settings.short_loss),
+        })
     }
-
     async fn get<T: IntoUrl + Copy>(&self, url: T) -> Response {
         req(&self.zulip.http, self.zulip.http.get(url), &self.token).await
     }
 
-    pub async fn get_arenas(&self) -> Arenas {
-        self.get("https://lichess.org/api/tournament")
+    async fn send(&self, builder: RequestBuilder, retry: Retry) -> Result<Response> {
+        match retry {
+            Retry::Forever => Ok(req(&self.zulip.http, builder, &self.token).await),
+            Retry::Never => Ok(req_once(&self.zulip.http, builder, &self.token).await?),
+        }
+    }
+
+    pub async fn get_arenas(&self) -> Result<Arenas> {
+        Ok(self
+            .get("https://lichess.org/api/tournament")
             .await
             .json::<Arenas>()
-            .await
-            .expect("Valid JSON Arena decoding")
+            .await?)
     }
 
-    pub async fn get_players(&self, arena: &Arena) -> impl Stream<Item = Player> {
+    // A malformed line only fails its player, a broken stream fails the rest of the arena
+    pub async fn get_players(&self, arena: &Arena) -> impl Stream<Item = Result<Player>> {
         // Thanks niklas, https://github.com/lichess-org/lila-openingexplorer/blob/d1b55a43eb4bbaace45c244d7f33d86b11c7ee41/src/indexer/lila.rs#L34-L73
         let stream = self
             .get(&format!(
@@ -133,120 +385,722 @@ impl Lichess {
             ))
             .await
             .bytes_stream()
-            .map_err(|err| io::Error::new(io::ErrorKind::Other, err));
+            .map_err(io::Error::other);
 
         Box::pin(
             LinesStream::new(StreamReader::new(stream).lines()).filter_map(|line| async move {
                 match line {
                     Ok(line) if line.is_empty() => None,
-                    Ok(line) => serde_json::from_str::<Player>(&line)
-                        .map_err(log_and_pass)
-                        .ok(),
-                    Err(err) => panic!("{err:?}"),
+                    Ok(line) => Some(serde_json::from_str::<Player>(&line).map_err(Error::from)),
+                    Err(err) => Some(Err(Error::Io(err))),
                 }
             }),
         )
     }
 
-    pub async fn get_users_info(&self, user_ids: &[&str]) -> Result<HashMap<String, User>, Error> {
-        self.post(
-            "https://lichess.org/api/users",
-            user_ids.iter().copied().take(300).collect::<String>(),
-        )
-        .await
-        .json::<Vec<User>>()
-        .await
-        .map_err(|err| {
-            warn!("{err}, requested user ids {user_ids:?}");
-            err
-        })
-        .map(|users| HashMap::from_iter(users.into_iter().map(|u| (u.id.to_string(), u))))
+    pub async fn get_users_info(
+        &self,
+        user_ids: &[&str],
+        retry: Retry,
+    ) -> Result<HashMap<String, User>> {
+        let mut users = HashMap::new();
+        let mut missing = vec![];
+        for user_id in user_ids {
+            let cached = self
+                .cache
+                .as_ref()
+                .and_then(|c| c.lock().unwrap().user(&user_id.to_lowercase()));
+            match cached {
+                Some(user) => {
+                    users.insert(user.id.clone(), user);
+                }
+                None => missing.push(*user_id),
+            }
+        }
+        if missing.is_empty() {
+            return Ok(users);
+        }
+        let fetched = self.fetch_users_info(&missing, retry).await?;
+        if let Some(cache) = &self.cache {
+            let mut cache = cache.lock().unwrap();
+            for user in fetched.values() {
+                cache.insert_user(user.clone())
+            }
+        }
+        users.extend(fetched);
+        Ok(users)
     }
 
-    pub async fn get_user_games(&self, user_id: &str, perf: &str) -> Option<MoveCounter> {
-        let last_6_months = (Utc::now() - chrono::Duration::days(180)).format("%Y-%m-%d");
-        let games = timeout(
-            Duration::from_secs(60),
-            self.get(
-            &format!("https://lichess.org/api/games/user/{user_id}?max=100&rated=true&perfType={perf}&ongoing=false&dateMin={last_6_months}")
-        ),
-        )
-        .await.ok()?
-        .text()
-        .await.ok()?;
-        Some(get_games(games, user_id))
+    async fn fetch_users_info(
+        &self,
+        user_ids: &[&str],
+        retry: Retry,
+    ) -> Result<HashMap<String, User>> {
+        Ok(self
+            .send(
+                self.zulip
+                    .http
+                    .post("https://lichess.org/api/users")
+                    .body(user_ids.iter().copied().take(300).collect::<String>()),
+                retry,
+            )
+            .await?
+            .json::<Vec<User>>()
+            .await
+            .map_err(|err| {
+                warn!("{err}, requested user ids {user_ids:?}");
+                err
+            })
+            .map(|users| HashMap::from_iter(users.into_iter().map(|u| (u.id.to_string(), u))))?)
+    }
+
+    pub async fn get_arena_games(&self, arena: &Arena, user_id: &str) -> Result<Vec<ArenaGame>> {
+        let url = format!(
+            "https://lichess.org/api/tournament/{}/games?player={user_id}",
+            arena.id
+        );
+        let builder = self
+            .zulip
+            .http
+            .get(&url)
+            .header(ACCEPT, "application/x-ndjson");
+        // an optional section of the report, not worth retrying
+        timeout(Duration::from_secs(60), self.send(builder, Retry::Never))
+            .await
+            .map_err(|_| Error::Timeout)??
+            .text()
+            .await?
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(|l| Ok(serde_json::from_str(l)?))
+            .collect()
+    }
+
+    // Only the games played since the last export are fetched if it is cached
+    pub async fn get_user_games(
+        &self,
+        user_id: &str,
+        perf: &str,
+        window: &GameWindow,
+        retry: Retry,
+    ) -> Result<MoveCounter> {
+        let key = games_key(user_id, perf, window.length());
+        // reprocessing old arenas would pollute the cache of recent ones
+        let cache = self
+            .cache
+            .as_ref()
+            .filter(|_| window.until > Utc::now() - chrono::Duration::days(1));
+        let lookup = cache
+            .map(|c| c.lock().unwrap().games(&key))
+            .unwrap_or(Lookup::Missing);
+        let since = match lookup {
+            Lookup::Fresh(games) => {
+                let mut counter = MoveCounter::new(user_id.to_string());
+                counter.games = window.filter(games);
+                return Ok(counter);
+            }
+            // games are filtered by creation date, some may have still been ongoing
+            Lookup::Stale(fetched_at) => window.since.max(fetched_at - chrono::Duration::hours(1)),
+            Lookup::Missing => window.since,
+        };
+        let mut counter = self
+            .export_games(
+                user_id,
+                perf,
+                (since, window.until),
+                window.max_games,
+                retry,
+            )
+            .await?;
+        if let Some(cache) = cache {
+            let games = cache.lock().unwrap().merge_games(
+                &key,
+                counter.games,
+                window.since,
+                window.max_games,
+            );
+            counter.games = window.filter(games);
+        }
+        Ok(counter)
+    }
+
+    // At most `max` games, most recent first, fetched a page at a time
+    async fn export_games(
+        &self,
+        user_id: &str,
+        perf: &str,
+        (since, mut until): (DateTime<Utc>, DateTime<Utc>),
+        max: usize,
+        retry: Retry,
+    ) -> Result<MoveCounter> {
+        let mut counter = MoveCounter::new(user_id.to_string());
+        loop {
+            let page_size = (max - counter.games.len()).min(GAMES_PAGE_SIZE);
+            let _timer = GAME_EXPORTS.start_timer();
+            let pgn = timeout(
+                Duration::from_secs(60),
+                self.send(self.zulip.http.get(
+                format!("https://lichess.org/api/games/user/{user_id}?max={page_size}&rated=true&perfType={perf}&ongoing=false&clocks=true&since={}&until={}", since.timestamp_millis(), until.timestamp_millis())
+            ), retry),
+            )
+            .await.map_err(|_| Error::Timeout)??
+            .text()
+            .await?;
+            let page = get_games(pgn, user_id)?.games;
+            let full = page.len() == page_size;
+            let oldest = page.last().and_then(GameResult::played_at);
+            counter.games.extend(page);
+            match oldest {
+                Some(oldest) if full && counter.games.len() < max && oldest < until => {
+                    until = oldest
+                }
+                _ => return Ok(counter),
+            }
+        }
+    }
+
+    fn save_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap().save()
+        }
     }
 
     pub async fn on_start(&self) {
-        self.zulip.start_message().await
+        let start_message = format!("(re)starting! commit {}", env!("GIT_HASH"));
+        info!("{}", &start_message);
+        for notifier in &self.notifiers {
+            notifier.start_message(&start_message).await
+        }
+    }
+
+    async fn post_status(&self, msg: &str) {
+        for notifier in &self.notifiers {
+            notifier.post_status(msg).await
+        }
+    }
+
+    fn sus_score(&self) -> SusScore {
+        *self.sus_score.lock().unwrap()
+    }
+
+    pub fn sleep_time(&self) -> Duration {
+        *self.sleep_time.lock().unwrap()
+    }
+
+    // Swap reloadable settings if the configuration changed and is valid, announcing it.
+    // Only `score`, `sleep_time`, `heartbeat`, `short_loss`, `loss_cluster`, `lookback`,
+    // `zulip.routes` and the digest interval and severity are reloaded, the rest is only
+    // read at startup
+    pub async fn reload_config(&self, watcher: &mut ConfigWatcher) {
+        if !watcher.changed() {
+            return;
+        }
+        let settings = match Settings::new(watcher.path()) {
+            Ok(settings) => settings,
+            Err(err) => {
+                return self
+                    .post_status(&format!("Configuration not reloaded: {err}"))
+                    .await
+            }
+        };
+        if let Err(errors) = settings.validate() {
+            return self
+                .post_status(&format!(
+                    "Configuration not reloaded:\n- {}",
+                    errors.join("\n- ")
+                ))
+                .await;
+        }
+        let mut changes = vec![];
+        changes.extend(swap("score thresholds", &self.sus_score, settings.score));
+        changes.extend(swap("sleep_time", &self.sleep_time, settings.sleep_time));
+        let heartbeat = settings
+            .heartbeat
+            .and_then(|h| chrono::Duration::from_std(h).ok());
+        let old_heartbeat = *self.heartbeat.lock().unwrap();
+        if old_heartbeat != heartbeat {
+            changes.push(format!(
+                "heartbeat (seconds): {:?} -> {:?}",
+                old_heartbeat.map(|h| h.num_seconds()),
+                heartbeat.map(|h| h.num_seconds())
+            ));
+            *self.heartbeat.lock().unwrap() = heartbeat;
+        }
+        changes.extend(swap("short_loss", &self.short_loss, settings.short_loss));
+        changes.extend(swap(
+            "loss_cluster",
+            &self.loss_cluster,
+            settings.loss_cluster,
+        ));
+        changes.extend(swap("lookback", &self.lookback, settings.lookback));
+        let routes = settings.zulip.routes().to_vec();
+        let old_routes = self.zulip.routes();
+        if old_routes != routes {
+            changes.push(format!("zulip.routes: {old_routes:?} -> {routes:?}"));
+            self.zulip.set_routes(routes)
+        }
+        // turning the digest on or off needs a restart
+        if let Some((digest, config)) = self.digest.as_ref().zip(settings.digest) {
+            changes.extend(digest.lock().unwrap().reload(config))
+        }
+        if changes.is_empty() {
+            info!("Configuration reloaded, nothing changed");
+        } else {
+            self.post_status(&format!(
+                "Configuration reloaded:\n- {}",
+                changes.join("\n- ")
+            ))
+            .await
+        }
+    }
+
+    pub async fn heartbeat(&self) {
+        let heartbeat = *self.heartbeat.lock().unwrap();
+        let msg = heartbeat.and_then(|interval| self.status.lock().unwrap().heartbeat(interval));
+        if let Some(msg) = msg {
+            info!("{msg}");
+            self.post_status(&msg).await
+        }
     }
 
+    // Forward alerts of `util::req` until the sender is dropped
+    pub async fn forward_alerts(&self, mut alerts: UnboundedReceiver<String>) {
+        while let Some(alert) = alerts.recv().await {
+            warn!("{alert}");
+            self.post_status(&alert).await
+        }
+    }
+
+    pub async fn on_shutdown(&self) {
+        self.save_cache();
+        let msg = format!("Shutting down. {}", self.status.lock().unwrap().message());
+        info!("{msg}");
+        self.post_status(&msg).await
+    }
+
+    #[instrument(name = "cycle", skip_all, fields(n = self.status.lock().unwrap().cycles + 1))]
     pub async fn watch(&self) {
         debug!("Start screening recent arenas");
-        for arena in self
-            .get_arenas()
-            .await
+        let _cycle_timer = CYCLE_DURATION.start_timer();
+        let arenas = match self.get_arenas().await {
+            Ok(arenas) => arenas,
+            Err(err) => {
+                warn!("Could not get arenas, skipping cycle: {err}");
+                return;
+            }
+        };
+        let arenas: Vec<Arena> = arenas
             .finished
-            .iter()
+            .into_iter()
             .filter(|a| a.has_max_rating)
+            .collect();
+        // resume the cycle interrupted by the last shutdown
+        let arena_ids: Vec<&str> = arenas.iter().map(|a| a.id.as_str()).collect();
+        let mut screened = vec![];
+        for (i, after_rank) in arenas_to_screen(self.cursor.load().as_ref(), &arena_ids) {
+            let arena = &arenas[i];
+            // the list does not have the conditions
+            let arena = match self.get_arena(&arena.id, Retry::Forever).await {
+                Ok(arena) => arena,
+                Err(err) => {
+                    warn!("Skipping arena {}: {err}", arena.id);
+                    continue;
+                }
+            };
+            self.screen_arena_after(&arena, after_rank, true).await;
+            if self.is_stopping() {
+                info!("Stopped screening in arena {}", arena.id);
+                return;
+            }
+            screened.push(arena);
+        }
+        self.cursor.clear();
         {
-            let mut stream = self.get_players(arena).await;
-            while let Some(player) = stream.next().await {
-                if self.preselect_player(arena, &player) {
-                    let sus_games = self
-                        .get_user_games(&player.username, &arena.perf.key)
-                        .await
-                        .unwrap_or_else(|| MoveCounter::new(player.username.clone()))
-                        .get_sorted_sus_games();
-                    if let Ok(user) = self.get_users_info(&[&player.username]).await {
-                        // TODO use tokio spawn?
-                        if self
-                            .sus_score
-                            .high
-                            .perf(&arena.schedule.speed)
-                            .map(|score| score <= player.score)
-                            .unwrap_or(false)
-                        {
-                            self.zulip.post_report(&player, arena, sus_games).await;
-                        // send to zulip if arena sort by itself is enough
-                        } else if user
-                            .get(&player.username)
-                            .map(User::is_new)
-                            .unwrap_or(false)
-                            || sus_games.len() > 25
-                            || arena
-                                .rating_limit()
-                                .zip(player.performance)
-                                .map(|(r, performance)| {
-                                    player.rating < r - 200 || performance > r + 500
-                                })
-                                .unwrap_or(false)
-                        {
-                            self.zulip.post_report(&player, arena, sus_games).await;
-                        } else if user
-                            .get(&player.username)
-                            .map(User::is_very_new) // different than above
-                            .unwrap_or(false)
-                            || sus_games.len() > 30
-                            || arena
-                                .rating_limit()
-                                .zip(player.performance)
-                                .map(|(r, performance)| {
-                                    player.rating < r - 300 || performance > r + 400
-                                })
-                                .unwrap_or(false)
-                        {
-                            self.zulip.post_report(&player, arena, sus_games).await;
-                        }
+            // arenas no longer listed will not be screened again
+            let mut reported = self.reported.lock().unwrap();
+            reported.retain(|(_, arena_id)| arena_ids.contains(&arena_id.as_str()));
+            self.cursor.save_reported(&reported);
+        }
+        self.save_cache();
+        *self.last_arenas.lock().unwrap() = screened;
+        debug!("Finished screening recent arenas");
+        self.status.lock().unwrap().cycles += 1;
+        let digest = self
+            .digest
+            .as_ref()
+            .and_then(|d| d.lock().unwrap().take_if_due(MAX_MESSAGE_LEN));
+        if let Some(table) = digest {
+            for notifier in &self.notifiers {
+                notifier.post_digest(&table).await
+            }
+        }
+        // nothing was posted to react to
+        if !(self.dry_run || self.zulip.is_muted()) {
+            self.zulip.poll_verdicts().await
+        }
+    }
+
+    // On demand, without moving the cursor of the cycle
+    pub async fn screen_arena(&self, arena: &Arena) {
+        self.screen_arena_after(arena, 0, false).await
+    }
+
+    // Screen players ranked after `after_rank`, until done or stopping
+    #[instrument(name = "arena", skip_all, fields(id = %arena.id, name = %arena.full_name))]
+    async fn screen_arena_after(&self, arena: &Arena, after_rank: u16, resumable: bool) {
+        let save_cursor = |rank| {
+            if resumable {
+                self.cursor.save(&Cursor {
+                    arena_id: arena.id.clone(),
+                    rank,
+                })
+            }
+        };
+        self.status.lock().unwrap().arenas += 1;
+        ARENAS_SCREENED.inc();
+        let mut stream = self.get_players(arena).await;
+        while let Some(player) = stream.next().await {
+            let player = match player {
+                Ok(player) => player,
+                Err(Error::Io(err)) => {
+                    warn!("Could not read the rest of the arena results: {err}");
+                    return;
+                }
+                Err(err) => {
+                    warn!("Skipping player: {err}");
+                    continue;
+                }
+            };
+            let rank = player.rank;
+            if rank <= after_rank {
+                continue;
+            }
+            if self.preselect_player(arena, &player) {
+                PLAYERS_PRESELECTED.inc();
+                self.screen_player(arena, player).await;
+                save_cursor(rank);
+            }
+            if self.is_stopping() {
+                save_cursor(rank);
+                return;
+            }
+        }
+    }
+
+    #[instrument(
+        name = "player",
+        skip_all,
+        fields(user = %player.username, rank = player.rank, score = player.score)
+    )]
+    async fn screen_player(&self, arena: &Arena, player: Player) {
+        let key = (player.username.to_lowercase(), arena.id.clone());
+        if self.reported.lock().unwrap().contains(&key) {
+            info!(decision = "skip", "Already reported for this arena");
+            return;
+        }
+        let window = GameWindow::for_arena(arena, self.lookback().speed(&arena.schedule.speed));
+        let sus_games = match self
+            .get_user_games(&player.username, &arena.perf.key, &window, Retry::Forever)
+            .await
+        {
+            Ok(games) => games.get_sorted_sus_games(&self.short_loss()),
+            Err(err) => {
+                warn!(decision = "skip", "Could not get games: {err}");
+                return;
+            }
+        };
+        let user = match self
+            .get_users_info(&[&player.username], Retry::Forever)
+            .await
+        {
+            Ok(user) => user,
+            Err(err) => {
+                warn!(decision = "skip", "Could not get user info: {err}");
+                return;
+            }
+        };
+        // TODO use tokio spawn?
+        let player_id = player.username.to_lowercase();
+        let (severity, rules) = self.evaluate(arena, &player, user.get(&player_id), &sus_games);
+        if rules.is_empty() {
+            info!(decision = "skip", %severity, losses = sus_games.len(), "No rule triggered");
+            return;
+        }
+        let digest = self
+            .digest
+            .as_ref()
+            .filter(|d| !d.lock().unwrap().is_immediate(severity));
+        info!(
+            decision = if digest.is_some() { "digest" } else { "report" },
+            %severity,
+            ?rules,
+            losses = sus_games.len(),
+            "Rules triggered"
+        );
+        if let Some(digest) = digest {
+            digest.lock().unwrap().push(DigestEntry {
+                user_id: player.username.clone(),
+                rating: player.rating,
+                score: player.score,
+                arena_id: arena.id.clone(),
+                arena_name: arena.full_name.clone(),
+                severity,
+                rules,
+                losses: sus_games.len(),
+            })
+        } else {
+            let arena_stats = match self.get_arena_games(arena, &player.username).await {
+                Ok(games) => Some(ArenaStats::new(&player, &games)),
+                Err(err) => {
+                    warn!("Could not get arena games: {err}");
+                    None
+                }
+            };
+            let report = Report::new(
+                player,
+                arena,
+                user.get(&player_id),
+                (sus_games, &window),
+                arena_stats,
+                (severity, rules),
+                &self.sus_score(),
+            );
+            for notifier in &self.notifiers {
+                notifier.post_report(&report).await
+            }
+            {
+                let mut reported = self.reported.lock().unwrap();
+                reported.insert(key);
+                self.cursor.save_reported(&reported);
+            }
+            self.status.lock().unwrap().reports += 1;
+            REPORTS
+                .with_label_values(&[&report.score.severity.to_string()])
+                .inc();
+            for rule in &report.score.rules {
+                REPORT_RULES.with_label_values(&[&rule.to_string()]).inc()
+            }
+            let mut recent = self.recent_reports.lock().unwrap();
+            if recent.len() == RECENT_REPORTS {
+                recent.pop_front();
+            }
+            recent.push_back(report);
+        }
+    }
+
+    pub async fn get_arena(&self, arena_id: &str, retry: Retry) -> Result<Arena> {
+        Ok(self
+            .send(
+                self.zulip
+                    .http
+                    .get(format!("https://lichess.org/api/tournament/{arena_id}")),
+                retry,
+            )
+            .await?
+            .json::<Arena>()
+            .await?)
+    }
+
+    pub fn recent_reports(&self) -> Vec<Report> {
+        self.recent_reports
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub fn last_arenas(&self) -> Vec<Arena> {
+        self.last_arenas.lock().unwrap().clone()
+    }
+
+    pub fn status_message(&self) -> String {
+        self.status.lock().unwrap().message()
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        info!("{} screening", if paused { "Pausing" } else { "Resuming" });
+        self.paused.store(paused, Ordering::Relaxed)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    // Ask `watch` to return after the current player
+    pub fn stop(&self) {
+        info!("Stopping after the current player");
+        self.stopping.store(true, Ordering::Relaxed);
+        self.stop.notify_waiters()
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::Relaxed)
+    }
+
+    pub async fn stopped(&self) {
+        let notified = self.stop.notified();
+        if !self.is_stopping() {
+            notified.await
+        }
+    }
+
+    // rules only based on the player's history, independent of any arena
+    fn history_rules(&self, user: Option<&User>, sus_games: &SusGames) -> Vec<Rule> {
+        let mut rules = vec![];
+        if user.map(User::is_new).unwrap_or(false) {
+            rules.push(Rule::NewAccount)
+        }
+        if self.short_loss().are_many(sus_games) {
+            rules.push(Rule::ManyLosses)
+        }
+        rules
+    }
+
+    fn arena_rules(
+        &self,
+        arena: &Arena,
+        player: &Player,
+        severity: Severity,
+        user: Option<&User>,
+        sus_games: &SusGames,
+    ) -> Vec<Rule> {
+        let mut rules = vec![];
+        // send to zulip if arena sort by itself is enough
+        if severity == Severity::High {
+            rules.push(Rule::HighScore)
+        }
+        rules.extend(self.history_rules(user, sus_games));
+        if user.map(|u| u.is_just_eligible(arena)).unwrap_or(false) {
+            rules.push(Rule::JustEligible)
+        }
+        let cluster = self
+            .loss_cluster()
+            .zip(arena.starts_at)
+            .and_then(|(config, starts_at)| config.detect(&sus_games.games, starts_at));
+        if let Some(cluster) = cluster {
+            debug!(
+                window_losses = cluster.window_losses,
+                baseline_losses = cluster.baseline_losses,
+                "Losses clustered before the arena"
+            );
+            rules.push(Rule::LossCluster)
+        }
+        if let Some(r) = arena.rating_limit() {
+            if player.rating < r.saturating_sub(200) {
+                rules.push(Rule::LowRating)
+            }
+            if player.performance.map(|p| p > r + 400).unwrap_or(false) {
+                rules.push(Rule::HighPerformance)
+            }
+        }
+        rules
+    }
+
+    // Same screening as `watch`, without the arena-based rules
+    pub async fn check_user(&self, user_id: &str, perf: &str) -> String {
+        let window = GameWindow::until_now(self.lookback().speed(perf));
+        let users = match self.get_users_info(&[user_id], Retry::Never).await {
+            Ok(users) => users,
+            Err(err) => return format!("Could not fetch {user_id}: {err}"),
+        };
+        let user = match users.get(user_id) {
+            Some(user) => user,
+            None => return format!("User {user_id} not found"),
+        };
+        let sus_games = match self
+            .get_user_games(user_id, perf, &window, Retry::Never)
+            .await
+        {
+            Ok(games) => games.get_sorted_sus_games(&self.short_loss()),
+            Err(err) if err.is_not_found() => return format!("User {user_id} not found"),
+            Err(err) => return format!("Could not fetch the games of {user_id}: {err}"),
+        };
+        let rules = self.history_rules(Some(user), &sus_games);
+        let verdict = if rules.is_empty() {
+            "nothing suspicious".to_string()
+        } else {
+            rules
+                .iter()
+                .map(Rule::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        format!(
+            "**[{user_id}](https://lichess.org/@/{user_id})** ({perf}): {verdict}\n{} suspicious losses in {} rated {perf} games, account created {}",
+            sus_games.len(),
+            sus_games.total,
+            user.created_at.format("%Y-%m-%d")
+        )
+    }
+
+    pub async fn listen_commands(&self) {
+        if !self.zulip.listens_commands() || self.dry_run {
+            return;
+        }
+        info!("Listening to zulip commands");
+        loop {
+            let mut queue = self.zulip.register_queue().await;
+            loop {
+                let events = match self.zulip.get_events(&mut queue).await {
+                    Ok(events) => events,
+                    Err(EventsError::QueueGone) => break,
+                    Err(EventsError::Other(err)) => {
+                        warn!("Could not get zulip events, retrying in a minute: {err}");
+                        sleep(Duration::from_secs(60)).await;
+                        continue;
                     }
+                };
+                for message in events {
+                    let reply = match Command::parse(&message.content) {
+                        Some(Command::Check { user_id, perf }) => {
+                            self.check_user(&user_id, &perf).await
+                        }
+                        Some(Command::Stats) => self.zulip.rule_stats_table(),
+                        Some(Command::Help) | None => USAGE.to_string(),
+                    };
+                    self.zulip.reply(&message, &reply).await;
                 }
             }
+            warn!("Zulip event queue expired, registering a new one");
         }
-        debug!("Finished screening recent arenas")
     }
 
-    fn preselect_player(&self, arena: &Arena, player: &Player) -> bool {
-        self.sus_score
+    // Severity and triggered rules of a preselected player, not to be reported if no rule is
+    pub fn evaluate(
+        &self,
+        arena: &Arena,
+        player: &Player,
+        user: Option<&User>,
+        sus_games: &SusGames,
+    ) -> (Severity, Vec<Rule>) {
+        let severity = self
+            .sus_score()
+            .severity(&arena.schedule.speed, player.score)
+            .unwrap_or(Severity::Low);
+        let rules = self.arena_rules(arena, player, severity, user, sus_games);
+        // a podium comes with a trophy, it matters more
+        if player.is_podium() && !rules.is_empty() {
+            (severity.escalate(), rules)
+        } else {
+            (severity, rules)
+        }
+    }
+
+    pub fn short_loss(&self) -> ShortLossConfig {
+        self.short_loss.lock().unwrap().clone()
+    }
+
+    pub fn lookback(&self) -> LookbackConfig {
+        *self.lookback.lock().unwrap()
+    }
+
+    fn loss_cluster(&self) -> Option<LossClusterConfig> {
+        self.loss_cluster.lock().unwrap().clone()
+    }
+
+    pub fn preselect_player(&self, arena: &Arena, player: &Player) -> bool {
+        self.sus_score()
             .low
             .perf(&arena.schedule.speed)
             .map(|score| score <= player.score)
@@ -257,42 +1111,72 @@ impl Lichess {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::setting::Settings;
-    use env_logger::{Builder, Target};
-    use log::LevelFilter;
+    use crate::{lookback::Lookback, setting::Settings, util::init_logs};
 
     #[test]
-    fn test_arena_rating_limit() {
-        let a = lichess::Arena {
-            has_max_rating: true,
-            full_name: "≤1500 Blitz Arena".to_string(),
-            ..Default::default()
+    fn test_arena_conditions() {
+        let a: Arena = serde_json::from_str(
+            r#"{"id":"xxxxxxxx","fullName":"Hourly Blitz Arena","perf":{"key":"blitz"},
+            "startsAt":"2022-03-04T12:00:00Z","maxRating":{"perf":"blitz","rating":1300},"minRatedGames":{"perf":"blitz","nb":20}}"#,
+        )
+        .expect("valid arena");
+        assert_eq!(a.rating_limit(), Some(1300));
+        assert_eq!(a.starts_at, Some("2022-03-04T12:00:00Z".parse().unwrap()));
+        assert_eq!(
+            a.conditions.describe(),
+            ["rating ≤ 1300", "20+ rated games"]
+        );
+        let user = User {
+            id: "german11".to_string(),
+            tos_violation: false,
+            created_at: Utc::now() - chrono::Duration::days(365),
+            perfs: HashMap::from([("blitz".to_string(), UserPerf { games: 25 })]),
         };
+        assert!(user.is_just_eligible(&a));
+    }
+
+    #[test]
+    fn test_arena_detail_conditions() {
+        let a: Arena = serde_json::from_str(ARENA_DETAIL).expect("valid arena");
+        assert_eq!(
+            a.conditions.describe(),
+            ["rating ≤ 1500", "20+ rated games"]
+        );
+        // shapes not seen so far
+        let mut json: serde_json::Value = serde_json::from_str(ARENA_DETAIL).unwrap();
+        json["teamMember"] = serde_json::json!("lichess-swiss");
+        json["minAccountAge"] = serde_json::json!({"days": 30});
+        let a: Arena = serde_json::from_value(json).expect("valid arena");
         assert_eq!(a.rating_limit(), Some(1500));
+        assert!(a.conditions.team_member.is_none());
+        assert!(a.conditions.min_account_age.is_none());
+    }
+
+    #[test]
+    fn test_arena_podium() {
+        let a: Arena = serde_json::from_str(
+            r#"{"id":"xxxxxxxx","fullName":"Hourly Blitz Arena","perf":{"key":"blitz"},
+            "podium":[{"name":"german11","rank":1,"rating":1290,"score":62,"performance":2050}]}"#,
+        )
+        .expect("valid arena");
+        assert_eq!(a.podium[0].username, "german11");
+        assert_eq!(a.starts_at, None);
+        assert!(a.podium[0].is_podium());
     }
 
     fn setup_lichess() -> Lichess {
-        let mut builder = Builder::new();
-        let s = Settings::new().expect("syntaxically correct config");
-        builder
-            .filter(
-                None,
-                if s.debug {
-                    LevelFilter::Debug
-                } else {
-                    LevelFilter::Info
-                },
-            )
-            .default_format()
-            .target(Target::Stdout)
-            .init();
-        Lichess::new(s)
+        let s = Settings::new(None).expect("syntaxically correct config");
+        init_logs(s.debug, s.json_logs, false);
+        Lichess::new(s).expect("valid template")
     }
 
     #[tokio::test]
     async fn test_get_user_games() {
         let l = setup_lichess();
-        l.get_user_games("german11", "bullet").await;
+        let window = GameWindow::until_now(Lookback::default());
+        l.get_user_games("german11", "bullet", &window, Retry::Forever)
+            .await
+            .expect("games of german11");
     }
 
     // #[tokio::test]
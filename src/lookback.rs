@@ -0,0 +1,154 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::{game_visitor::GameResult, lichess::Arena};
+
+// Games of a player looked at for one speed
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct Lookback {
+    // before the arena start
+    pub days: u16,
+    pub max_games: usize,
+}
+
+impl Default for Lookback {
+    fn default() -> Self {
+        Self {
+            days: 180,
+            max_games: 100,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct LookbackConfig {
+    pub bullet: Lookback,
+    pub super_blitz: Lookback,
+    pub blitz: Lookback,
+    pub rapid: Lookback,
+}
+
+impl LookbackConfig {
+    // `speed` of the arena schedule, or a perf key
+    pub fn speed(&self, speed: &str) -> Lookback {
+        match speed {
+            "bullet" => self.bullet,
+            "superBlitz" => self.super_blitz,
+            "blitz" => self.blitz,
+            "rapid" => self.rapid,
+            _ => Lookback::default(),
+        }
+    }
+
+    pub fn validate(&self, errors: &mut Vec<String>) {
+        for speed in ["bullet", "superBlitz", "blitz", "rapid"] {
+            let lookback = self.speed(speed);
+            if lookback.days == 0 || lookback.max_games == 0 {
+                errors.push(format!(
+                    "lookback: {speed} days and max_games should not be 0"
+                ))
+            }
+        }
+    }
+}
+
+// Period in which the games of a player are fetched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameWindow {
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub max_games: usize,
+}
+
+impl GameWindow {
+    // `lookback.days` before the arena start, until it finishes
+    pub fn for_arena(arena: &Arena, lookback: Lookback) -> Self {
+        let until = arena.ends_at().unwrap_or_else(Utc::now);
+        Self {
+            since: arena.starts_at.unwrap_or(until) - chrono::Duration::days(lookback.days.into()),
+            until,
+            max_games: lookback.max_games,
+        }
+    }
+
+    pub fn until_now(lookback: Lookback) -> Self {
+        let until = Utc::now();
+        Self {
+            since: until - chrono::Duration::days(lookback.days.into()),
+            until,
+            max_games: lookback.max_games,
+        }
+    }
+
+    pub fn length(&self) -> chrono::Duration {
+        self.until - self.since
+    }
+
+    // games outside the window, or without a date, are dropped
+    pub fn filter(&self, games: Vec<GameResult>) -> Vec<GameResult> {
+        let mut games: Vec<GameResult> = games
+            .into_iter()
+            .filter(|g| {
+                g.played_at()
+                    .map(|t| self.since <= t && t <= self.until)
+                    .unwrap_or(false)
+            })
+            .collect();
+        games.truncate(self.max_games);
+        games
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lichess::ARENA_DETAIL;
+
+    fn game(date: &str) -> GameResult {
+        GameResult {
+            date: date.to_string(),
+            ..GameResult::sample()
+        }
+    }
+
+    #[test]
+    fn test_window_for_arena() {
+        let arena = Arena {
+            starts_at: Some("2022-03-04T12:00:00Z".parse().unwrap()),
+            finishes_at: Some("2022-03-04T13:00:00Z".parse().unwrap()),
+            ..Default::default()
+        };
+        let window = GameWindow::for_arena(
+            &arena,
+            Lookback {
+                days: 30,
+                max_games: 2,
+            },
+        );
+        assert_eq!(
+            window.since,
+            "2022-02-02T12:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        let games = window.filter(vec![
+            game("2022.03.05"),
+            game("2022.03.04"),
+            game("2022.03.01"),
+            game("2022.02.20"),
+        ]);
+        let dates: Vec<&str> = games.iter().map(|g| g.date.as_str()).collect();
+        assert_eq!(dates, ["2022.03.04", "2022.03.01"]);
+    }
+
+    #[test]
+    fn test_window_for_arena_detail() {
+        let arena: Arena = serde_json::from_str(ARENA_DETAIL).expect("valid arena");
+        let window = GameWindow::for_arena(&arena, Lookback::default());
+        // 57 minutes after the start, not now
+        assert_eq!(
+            window.until,
+            "2022-03-04T12:57:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+}
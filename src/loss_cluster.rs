@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_with::{serde_as, DurationSeconds};
+
+use crate::game_visitor::GameResult;
+
+// Burst of losses right before an arena, compared to the weeks before
+#[serde_as]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct LossClusterConfig {
+    // time in seconds before the arena start where losses are counted
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub window: Duration,
+    // time in seconds before `window` giving the usual loss density
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub baseline: Duration,
+    // losses in the window needed, whatever the baseline
+    pub min_losses: usize,
+    // how many times denser than the baseline losses must be in the window
+    pub ratio: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LossCluster {
+    pub window_losses: usize,
+    pub baseline_losses: usize,
+}
+
+impl LossClusterConfig {
+    pub fn validate(&self, errors: &mut Vec<String>) {
+        if self.window.is_zero() || self.baseline.is_zero() {
+            errors.push("loss_cluster.window and baseline should not be 0".to_string())
+        }
+        if self.ratio <= 0. {
+            errors.push("loss_cluster.ratio should be positive".to_string())
+        }
+    }
+
+    // `sus_games` are the player's losses, games without a time are ignored
+    pub fn detect(
+        &self,
+        sus_games: &[GameResult],
+        starts_at: DateTime<Utc>,
+    ) -> Option<LossCluster> {
+        let window = chrono::Duration::from_std(self.window).ok()?;
+        let baseline = chrono::Duration::from_std(self.baseline).ok()?;
+        let window_start = starts_at - window;
+        let baseline_start = window_start - baseline;
+        let (mut window_losses, mut baseline_losses) = (0, 0);
+        for played_at in sus_games.iter().filter_map(GameResult::played_at) {
+            if window_start <= played_at && played_at < starts_at {
+                window_losses += 1
+            } else if baseline_start <= played_at && played_at < window_start {
+                baseline_losses += 1
+            }
+        }
+        let density = |losses: usize, span: Duration| losses as f64 / span.as_secs_f64();
+        let spikes = density(window_losses, self.window)
+            >= self.ratio * density(baseline_losses, self.baseline);
+        (window_losses >= self.min_losses && spikes).then_some(LossCluster {
+            window_losses,
+            baseline_losses,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn loss(date: &str, time: &str) -> GameResult {
+        GameResult {
+            date: date.to_string(),
+            time: time.to_string(),
+            ..GameResult::sample()
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        let config = LossClusterConfig {
+            window: Duration::from_secs(6 * 3600),
+            baseline: Duration::from_secs(7 * 24 * 3600),
+            min_losses: 3,
+            ratio: 5.,
+        };
+        let starts_at = "2022-03-04T12:00:00Z".parse().unwrap();
+        let mut games = vec![
+            loss("2022.03.04", "07:00:00"),
+            loss("2022.03.04", "08:00:00"),
+            loss("2022.03.04", "11:00:00"),
+            loss("2022.03.01", "11:00:00"),
+            // after the start, not counted
+            loss("2022.03.04", "13:00:00"),
+        ];
+        assert_eq!(
+            config.detect(&games, starts_at),
+            Some(LossCluster {
+                window_losses: 3,
+                baseline_losses: 1,
+            })
+        );
+        // a usual day for this player
+        games.extend((10..=29).map(|m| loss("2022.03.02", &format!("10:{m}:00"))));
+        assert_eq!(config.detect(&games, starts_at), None);
+    }
+}
@@ -1,38 +1,190 @@
-use env_logger::{Builder, Target};
-use log::{debug, LevelFilter};
+use std::{path::PathBuf, process::ExitCode, sync::Arc};
 
+use clap::{Parser, Subcommand};
+use tracing::{debug, error, info, warn};
+
+mod admin;
+mod arena_stats;
+mod backtest;
+mod cache;
+mod command;
+mod cursor;
+mod digest;
+mod error;
 mod game_visitor;
 mod lichess;
+mod lookback;
+mod loss_cluster;
+mod metrics;
+mod notifier;
+mod reload;
+mod report;
+mod rule;
 mod score;
 mod setting;
+mod short_loss;
+mod status;
 mod util;
+mod verdict;
 mod zulip;
 
 use tokio::time::sleep;
 
-use crate::{lichess::Lichess, setting::Settings};
+use crate::{
+    lichess::{Lichess, Retry},
+    reload::ConfigWatcher,
+    setting::Settings,
+    util::{alerts, init_logs, shutdown_signal},
+};
+
+#[derive(Parser)]
+#[command(version, about = "Watch and report suspicious tournament performance")]
+struct Cli {
+    /// Configuration file to use instead of `config/base` and `config/prod`
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    action: Option<Action>,
+}
+
+#[derive(Subcommand)]
+enum Action {
+    /// Screen recent arenas forever (default)
+    Run,
+    /// Screen a single arena and exit
+    ScanArena { id: String },
+    /// Print the screening of a user's history
+    CheckUser {
+        name: String,
+        #[arg(long, default_value = "blitz")]
+        perf: String,
+    },
+    /// Replay the screening on arenas saved in a directory (`arenas.json`, `{id}.ndjson`, `games/{user}.{perf}.pgn`, `users.json`)
+    Backtest { dir: PathBuf },
+    /// Check the configuration and templates, then exit
+    ValidateConfig,
+}
 
 #[tokio::main]
-async fn main() {
-    let mut builder = Builder::new();
-    let s = Settings::new().expect("syntaxically correct config");
-    builder
-        .filter(
-            None,
-            if s.debug {
-                LevelFilter::Debug
-            } else {
-                LevelFilter::Info
-            },
-        )
-        .default_format()
-        .target(Target::Stdout)
-        .init();
-    let lichess = Lichess::new(s.clone());
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let s = match Settings::new(cli.config.as_deref()) {
+        Ok(s) => s,
+        Err(err) => {
+            eprintln!("Invalid configuration: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    // before anything gets posted
+    if let Err(errors) = s.validate() {
+        eprintln!("Invalid configuration:");
+        for err in errors {
+            eprintln!("- {err}")
+        }
+        return ExitCode::FAILURE;
+    }
+    let action = cli.action.unwrap_or(Action::Run);
+    // reports of a dry run without a file, backtest lines and checks are printed
+    let stdout_data = s
+        .dry_run
+        .as_ref()
+        .map(|d| d.path.is_none())
+        .unwrap_or(false)
+        || matches!(action, Action::CheckUser { .. } | Action::Backtest { .. });
+    init_logs(s.debug, s.json_logs, stdout_data);
+    let lichess = match Lichess::new(s.clone()) {
+        Ok(lichess) => lichess,
+        Err(err) => {
+            eprintln!("Invalid configuration: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match action {
+        Action::Run => run(lichess, s, cli.config).await,
+        Action::ScanArena { id } => match lichess.get_arena(&id, Retry::Never).await {
+            Ok(arena) => lichess.screen_arena(&arena).await,
+            Err(err) if err.is_not_found() => {
+                error!("Arena {id} not found");
+                return ExitCode::FAILURE;
+            }
+            Err(err) => {
+                error!("Could not get arena {id}: {err}");
+                return ExitCode::FAILURE;
+            }
+        },
+        Action::CheckUser { name, perf } => {
+            println!("{}", lichess.check_user(&name.to_lowercase(), &perf).await)
+        }
+        Action::Backtest { dir } => match backtest::backtest(&lichess, &dir) {
+            Ok(reports) => info!("{reports} players would have been reported"),
+            Err(err) => {
+                error!("Could not backtest {dir:?}: {err}");
+                return ExitCode::FAILURE;
+            }
+        },
+        Action::ValidateConfig => println!("Configuration is valid"),
+    }
+    ExitCode::SUCCESS
+}
+
+async fn run(lichess: Lichess, s: Settings, config: Option<PathBuf>) {
+    let alerts = alerts();
+    let lichess = Arc::new(lichess);
     lichess.on_start().await;
-    loop {
-        lichess.watch().await;
-        debug!("Waiting {:?} before screening Arenas again.", &s.sleep_time);
-        sleep(s.sleep_time).await;
+    let mut watcher = ConfigWatcher::new(config);
+    let watch_loop = async {
+        while !lichess.is_stopping() {
+            if lichess.is_paused() {
+                debug!("Paused, not screening arenas");
+            } else {
+                lichess.watch().await;
+            }
+            lichess.heartbeat().await;
+            lichess.reload_config(&mut watcher).await;
+            let sleep_time = lichess.sleep_time();
+            debug!("Waiting {:?} before screening Arenas again.", &sleep_time);
+            tokio::select! {
+                _ = sleep(sleep_time) => (),
+                _ = lichess.stopped() => (),
+            }
+        }
+    };
+    // the current player is screened before stopping, unless a second signal comes first
+    // since a failing request can be retried for up to an hour
+    let graceful_stop = async {
+        tokio::select! {
+            _ = watch_loop => false,
+            _ = async {
+                shutdown_signal().await;
+                lichess.stop();
+                shutdown_signal().await
+            } => {
+                warn!("Stopping right away, the current player is not screened");
+                true
+            }
+        }
+    };
+    let forced = tokio::select! {
+        _ = async {
+            tokio::join!(
+                lichess.listen_commands(),
+                lichess.forward_alerts(alerts),
+                async {
+                    if let Some(addr) = s.metrics_addr {
+                        metrics::serve(addr).await
+                    }
+                },
+                async {
+                    if let Some(config) = s.admin.clone() {
+                        admin::serve(config, lichess.clone()).await
+                    }
+                }
+            )
+        } => false,
+        forced = graceful_stop => forced,
+    };
+    // posting the status could hang as well
+    if !forced {
+        lichess.on_shutdown().await
     }
 }
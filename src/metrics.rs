@@ -0,0 +1,100 @@
+use std::{net::SocketAddr, sync::LazyLock};
+
+use axum::{routing::get, Router};
+use prometheus::{
+    register_histogram, register_int_counter, register_int_counter_vec, Encoder, Histogram,
+    IntCounter, IntCounterVec, TextEncoder,
+};
+use tracing::{error, info};
+
+pub static ARENAS_SCREENED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("sandbag_arenas_screened_total", "Arenas screened").unwrap()
+});
+
+pub static PLAYERS_PRESELECTED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "sandbag_players_preselected_total",
+        "Players whose arena score reached the low threshold"
+    )
+    .unwrap()
+});
+
+pub static GAME_EXPORTS: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "sandbag_game_export_duration_seconds",
+        "Latency of user game exports",
+        vec![0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]
+    )
+    .unwrap()
+});
+
+pub static HTTP_RETRIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "sandbag_http_retries_total",
+        "Failed HTTP requests retried, per host",
+        &["host"]
+    )
+    .unwrap()
+});
+
+pub static REPORTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "sandbag_reports_total",
+        "Reports sent, per severity",
+        &["severity"]
+    )
+    .unwrap()
+});
+
+pub static REPORT_RULES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "sandbag_report_rules_total",
+        "Rules triggered by reports sent",
+        &["rule"]
+    )
+    .unwrap()
+});
+
+pub static CYCLE_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "sandbag_cycle_duration_seconds",
+        "Duration of a screening of all recent arenas",
+        vec![10.0, 30.0, 60.0, 300.0, 900.0, 1800.0, 3600.0, 7200.0]
+    )
+    .unwrap()
+});
+
+async fn metrics() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("metrics encoding");
+    String::from_utf8(buffer).expect("utf8 metrics")
+}
+
+// Serve `/metrics` until the process stops
+pub async fn serve(addr: SocketAddr) {
+    let app = Router::new().route("/metrics", get(metrics));
+    match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => {
+            info!("Serving metrics on http://{addr}/metrics");
+            if let Err(err) = axum::serve(listener, app).await {
+                error!("Metrics server error: {err}")
+            }
+        }
+        Err(err) => error!("Could not bind metrics server to {addr}: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_metrics() {
+        REPORTS.with_label_values(&["high"]).inc();
+        assert!(metrics()
+            .await
+            .contains(r#"sandbag_reports_total{severity="high"}"#));
+    }
+}
@@ -0,0 +1,254 @@
+use std::{
+    fs::OpenOptions,
+    io::Write as _,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, warn};
+
+use crate::{
+    report::{Report, ReportTemplate},
+    util::{check_url, req_once, truncate, Auth},
+};
+
+// Where reports end up
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn start_message(&self, msg: &str);
+    async fn post_report(&self, report: &Report);
+    async fn post_digest(&self, table: &str);
+    // heartbeats, alerts and shutdown
+    async fn post_status(&self, msg: &str);
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SinkConfig {
+    Discord {
+        url: String,
+        #[serde(default)]
+        template: Option<PathBuf>,
+    },
+    Slack {
+        url: String,
+        #[serde(default)]
+        template: Option<PathBuf>,
+    },
+    #[serde(rename_all = "snake_case")]
+    Matrix {
+        // eg: https://matrix.org
+        homeserver: String,
+        room_id: String,
+        access_token: String,
+        #[serde(default)]
+        template: Option<PathBuf>,
+    },
+    // POST of `{"event": ..., "text": ..., "report": ...}`, `report` being the template context
+    Webhook {
+        url: String,
+        #[serde(default)]
+        template: Option<PathBuf>,
+    },
+}
+
+impl SinkConfig {
+    fn name(&self) -> &'static str {
+        match self {
+            SinkConfig::Discord { .. } => "discord",
+            SinkConfig::Slack { .. } => "slack",
+            SinkConfig::Matrix { .. } => "matrix",
+            SinkConfig::Webhook { .. } => "webhook",
+        }
+    }
+
+    pub fn validate(&self, errors: &mut Vec<String>) {
+        let url = match self {
+            SinkConfig::Discord { url, .. }
+            | SinkConfig::Slack { url, .. }
+            | SinkConfig::Webhook { url, .. } => url,
+            SinkConfig::Matrix { homeserver, .. } => homeserver,
+        };
+        if let Err(err) = check_url(url) {
+            errors.push(format!("sinks ({}): {err}", self.name()))
+        }
+        if let Err(err) = ReportTemplate::new(self.template().map(PathBuf::as_path)) {
+            errors.push(format!("sinks ({}): {err}", self.name()))
+        }
+    }
+
+    fn template(&self) -> Option<&PathBuf> {
+        match self {
+            SinkConfig::Discord { template, .. }
+            | SinkConfig::Slack { template, .. }
+            | SinkConfig::Matrix { template, .. }
+            | SinkConfig::Webhook { template, .. } => template.as_ref(),
+        }
+    }
+}
+
+pub struct Webhook {
+    http: Client,
+    config: SinkConfig,
+    template: ReportTemplate,
+}
+
+impl Webhook {
+    pub fn new(config: SinkConfig) -> Result<Self, String> {
+        let template = ReportTemplate::new(config.template().map(PathBuf::as_path))
+            .map_err(|err| format!("sinks ({}): {err}", config.name()))?;
+        Ok(Self {
+            http: Client::new(),
+            config,
+            template,
+        })
+    }
+
+    async fn send(&self, event: &str, text: &str, report: Option<&Report>) {
+        let (builder, auth) = match &self.config {
+            // discord rejects messages above 2000 characters
+            SinkConfig::Discord { url, .. } => (
+                self.http
+                    .post(url)
+                    .json(&json!({ "content": truncate(text, 2000) })),
+                None,
+            ),
+            SinkConfig::Slack { url, .. } => {
+                (self.http.post(url).json(&json!({ "text": text })), None)
+            }
+            SinkConfig::Matrix {
+                homeserver,
+                room_id,
+                access_token,
+                ..
+            } => {
+                let txn_id = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+                (
+                    self.http
+                        .put(format!(
+                            "{homeserver}/_matrix/client/v3/rooms/{room_id}/send/m.room.message/{txn_id}"
+                        ))
+                        .json(&json!({ "msgtype": "m.text", "body": text })),
+                    Some(Auth::Bearer(access_token.clone())),
+                )
+            }
+            SinkConfig::Webhook { url, .. } => (
+                self.http
+                    .post(url)
+                    .json(&json!({ "event": event, "text": text, "report": report })),
+                None,
+            ),
+        };
+        debug!("{event} sent to {}: {text}", self.config.name());
+        // a third-party sink being down must not hold the screening back
+        if let Err(err) = req_once(&self.http, builder, &auth).await {
+            warn!("Could not send {event} to {}: {err}", self.config.name())
+        }
+    }
+}
+
+// Run without posting anything, reports are written as JSON lines instead
+#[derive(Debug, Deserialize, Clone)]
+pub struct DryRunConfig {
+    // append-only JSONL file, stdout if `None`, logs then go to stderr
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub template: Option<PathBuf>,
+}
+
+impl DryRunConfig {
+    pub fn validate(&self, errors: &mut Vec<String>) {
+        if let Err(err) = ReportTemplate::new(self.template.as_deref()) {
+            errors.push(format!("dry_run: {err}"))
+        }
+    }
+}
+
+pub struct DryRun {
+    path: Option<PathBuf>,
+    template: ReportTemplate,
+}
+
+impl DryRun {
+    pub fn new(config: DryRunConfig) -> Result<Self, String> {
+        let template = ReportTemplate::new(config.template.as_deref())
+            .map_err(|err| format!("dry_run: {err}"))?;
+        Ok(Self {
+            path: config.path,
+            template,
+        })
+    }
+
+    fn write(&self, line: Value) {
+        match &self.path {
+            Some(path) => {
+                if let Err(err) = append_line(path, &line.to_string()) {
+                    warn!("Could not write to {path:?}: {err}")
+                }
+            }
+            None => println!("{line}"),
+        }
+    }
+}
+
+fn append_line(path: &Path, line: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{line}")
+}
+
+#[async_trait]
+impl Notifier for DryRun {
+    async fn start_message(&self, msg: &str) {
+        self.write(json!({ "event": "start", "at": Utc::now(), "text": msg }))
+    }
+
+    async fn post_report(&self, report: &Report) {
+        match self.template.render(report) {
+            Ok(text) => self.write(
+                json!({ "event": "report", "at": Utc::now(), "text": text, "report": report }),
+            ),
+            Err(err) => warn!(
+                "Could not render report of {}: {err}",
+                report.player.username
+            ),
+        }
+    }
+
+    async fn post_digest(&self, table: &str) {
+        self.write(json!({ "event": "digest", "at": Utc::now(), "text": table }))
+    }
+
+    async fn post_status(&self, msg: &str) {
+        self.write(json!({ "event": "status", "at": Utc::now(), "text": msg }))
+    }
+}
+
+#[async_trait]
+impl Notifier for Webhook {
+    async fn start_message(&self, msg: &str) {
+        self.send("start", msg, None).await
+    }
+
+    async fn post_report(&self, report: &Report) {
+        match self.template.render(report) {
+            Ok(text) => self.send("report", &text, Some(report)).await,
+            Err(err) => warn!(
+                "Could not render report of {}: {err}",
+                report.player.username
+            ),
+        }
+    }
+
+    async fn post_digest(&self, table: &str) {
+        self.send("digest", table, None).await
+    }
+
+    async fn post_status(&self, msg: &str) {
+        self.send("status", msg, None).await
+    }
+}
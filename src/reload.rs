@@ -0,0 +1,74 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info};
+
+// Tells when the configuration files changed, or a SIGHUP was received
+pub struct ConfigWatcher {
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    hangup: Arc<AtomicBool>,
+}
+
+impl ConfigWatcher {
+    // `path` is the `--config` file, `config/base.toml` and `config/prod.toml` are watched if `None`
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let hangup = Arc::new(AtomicBool::new(false));
+        let flag = hangup.clone();
+        tokio::spawn(async move {
+            match signal(SignalKind::hangup()) {
+                Ok(mut sighup) => {
+                    while sighup.recv().await.is_some() {
+                        info!("SIGHUP received, reloading configuration after this cycle");
+                        flag.store(true, Ordering::Relaxed)
+                    }
+                }
+                Err(err) => error!("Could not listen to SIGHUP: {err}"),
+            }
+        });
+        let mut watcher = Self {
+            path,
+            modified: None,
+            hangup,
+        };
+        watcher.modified = watcher.last_modified();
+        watcher
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn last_modified(&self) -> Option<SystemTime> {
+        let files = match &self.path {
+            Some(path) => vec![path.clone()],
+            None => vec![
+                PathBuf::from("config/base.toml"),
+                PathBuf::from("config/prod.toml"),
+            ],
+        };
+        files
+            .iter()
+            .filter_map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+            .max()
+    }
+
+    pub fn changed(&mut self) -> bool {
+        let modified = self.last_modified();
+        let hangup = self.hangup.swap(false, Ordering::Relaxed);
+        if hangup || modified > self.modified {
+            self.modified = modified;
+            true
+        } else {
+            false
+        }
+    }
+}
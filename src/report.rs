@@ -0,0 +1,246 @@
+use std::{fs, path::Path};
+
+use chrono::Utc;
+use minijinja::{Environment, UndefinedBehavior};
+use serde::Serialize;
+
+use crate::{
+    arena_stats::ArenaStats,
+    game_visitor::GameResult,
+    lichess::{Arena, Conditions, Perf, Player, RatingCondition, Schedule, User},
+    lookback::GameWindow,
+    rule::Rule,
+    score::{Severity, SusScore},
+    short_loss::SusGames,
+    util::perf_to_index,
+};
+
+const DEFAULT_TEMPLATE: &str = include_str!("../config/report.md");
+
+// Everything a report template has access to, fields are camelCased like in lichess API
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub player: Player,
+    // rank 1 to 3, gets a trophy
+    pub podium: bool,
+    pub arena: Arena,
+    pub rating_limit: Option<u16>,
+    // entry requirements, eg: `rating ≤ 1500`
+    pub conditions: Vec<String>,
+    pub user: Option<UserContext>,
+    // suspicious losses, shortest first
+    pub games: Vec<GameResult>,
+    // games in the window, suspicious or not
+    pub total_games: usize,
+    // games played in the arena itself, `None` if they could not be fetched
+    pub arena_stats: Option<ArenaStats>,
+    pub score: ScoreBreakdown,
+    pub perf_index: String,
+    // period searched for games, `YYYY-MM-DD`
+    pub date_min: String,
+    pub date_max: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UserContext {
+    pub id: String,
+    pub created_at: String,
+    pub is_new: bool,
+    pub tos_violation: bool,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ScoreBreakdown {
+    pub severity: Severity,
+    pub rules: Vec<Rule>,
+    pub rule_names: Vec<String>,
+    // thresholds of the arena speed
+    pub low: Option<u16>,
+    pub medium: Option<u16>,
+    pub high: Option<u16>,
+}
+
+impl Report {
+    pub fn new(
+        player: Player,
+        arena: &Arena,
+        user: Option<&User>,
+        (sus_games, window): (SusGames, &GameWindow),
+        arena_stats: Option<ArenaStats>,
+        (severity, rules): (Severity, Vec<Rule>),
+        sus_score: &SusScore,
+    ) -> Self {
+        let speed = &arena.schedule.speed;
+        Self {
+            podium: player.is_podium(),
+            rating_limit: arena.rating_limit(),
+            conditions: arena.conditions.describe(),
+            user: user.map(|u| UserContext {
+                id: u.id.clone(),
+                created_at: u.created_at.format("%Y-%m-%d").to_string(),
+                is_new: u.is_new(),
+                tos_violation: u.tos_violation,
+            }),
+            games: sus_games.games,
+            total_games: sus_games.total,
+            arena_stats,
+            score: ScoreBreakdown {
+                severity,
+                rule_names: rules.iter().map(Rule::to_string).collect(),
+                rules,
+                low: sus_score.low.perf(speed),
+                medium: sus_score.medium.perf(speed),
+                high: sus_score.high.perf(speed),
+            },
+            perf_index: perf_to_index(&arena.perf.key)
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "?".to_string()),
+            date_min: window.since.format("%Y-%m-%d").to_string(),
+            date_max: window.until.format("%Y-%m-%d").to_string(),
+            player,
+            arena: arena.clone(),
+        }
+    }
+
+    // used to check templates at startup
+    fn sample() -> Self {
+        Self {
+            player: Player {
+                rank: 1,
+                score: 60,
+                rating: 1300,
+                username: "german11".to_string(),
+                performance: Some(2100),
+            },
+            podium: true,
+            arena: Arena {
+                id: "xxxxxxxx".to_string(),
+                has_max_rating: true,
+                schedule: Schedule {
+                    freq: "hourly".to_string(),
+                    speed: "blitz".to_string(),
+                },
+                perf: Perf {
+                    key: "blitz".to_string(),
+                },
+                full_name: "≤1500 Blitz Arena".to_string(),
+                starts_at: Some(Utc::now()),
+                finishes_at: Some(Utc::now()),
+                minutes: None,
+                conditions: Conditions {
+                    max_rating: Some(RatingCondition { rating: 1500 }),
+                    ..Default::default()
+                },
+                podium: vec![Player {
+                    rank: 1,
+                    score: 60,
+                    rating: 1300,
+                    username: "german11".to_string(),
+                    performance: Some(2100),
+                }],
+            },
+            rating_limit: Some(1500),
+            conditions: vec!["rating ≤ 1500".to_string()],
+            user: Some(UserContext {
+                id: "german11".to_string(),
+                created_at: "2022-01-01".to_string(),
+                is_new: true,
+                tos_violation: false,
+            }),
+            games: vec![GameResult {
+                id: "xxxxxxxx".to_string(),
+                moves: 12,
+                won: false,
+                is_white: true,
+                opponent: "opponent".to_string(),
+                rating: None,
+                opponent_rating: Some(1450),
+                termination: "Normal".to_string(),
+                clock_left: Some("0:02:58".to_string()),
+                rating_diff: Some(-5),
+                date: "2022.03.04".to_string(),
+                time: "10:00:00".to_string(),
+            }],
+            total_games: 100,
+            arena_stats: Some(ArenaStats {
+                games: 12,
+                wins: 11,
+                draws: 0,
+                losses: 1,
+                berserk_percent: 75,
+                longest_win_streak: 9,
+                avg_opponent_rating: Some(1450),
+                max_opponent_rating: Some(1620),
+                avg_duration_secs: Some(95),
+                performance: Some(2200),
+                performance_gap: Some(900),
+            }),
+            score: ScoreBreakdown {
+                severity: Severity::High,
+                rules: vec![Rule::HighScore],
+                rule_names: vec![Rule::HighScore.to_string()],
+                low: Some(25),
+                medium: Some(30),
+                high: Some(50),
+            },
+            perf_index: "2".to_string(),
+            date_min: "2022-01-01".to_string(),
+            date_max: "2022-06-30".to_string(),
+        }
+    }
+}
+
+pub struct ReportTemplate {
+    env: Environment<'static>,
+}
+
+impl ReportTemplate {
+    // Load the template at `path`, or the default one, and check it renders
+    pub fn new(path: Option<&Path>) -> Result<Self, String> {
+        let source = match path {
+            Some(path) => fs::read_to_string(path)
+                .map_err(|err| format!("cannot read report template {path:?}: {err}"))?,
+            None => DEFAULT_TEMPLATE.to_string(),
+        };
+        let mut env = Environment::new();
+        // catch typos in field names
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.add_template_owned("report", source)
+            .map_err(|err| format!("invalid report template: {err:#}"))?;
+        let template = Self { env };
+        template
+            .render(&Report::sample())
+            .map_err(|err| format!("invalid report template: {err}"))?;
+        Ok(template)
+    }
+
+    pub fn render(&self, report: &Report) -> Result<String, String> {
+        self.env
+            .get_template("report")
+            .and_then(|t| t.render(report))
+            .map_err(|err| format!("{err:#}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_template() {
+        let msg = ReportTemplate::new(None)
+            .unwrap()
+            .render(&Report::sample())
+            .unwrap();
+        assert!(msg.contains(
+            "**[german11 (1300)](https://lichess.org/@/german11)** (high severity: high score)"
+        ));
+        assert!(msg.contains(
+            "| [6](<https://lichess.org/xxxxxxxx#12>) | opponent (1450) | white | Normal | 0:02:58 | -5 | 2022.03.04 |"
+        ));
+        assert!(msg.contains(":trophy: **Podium, rank 1** of 1. german11 (1300, 60 points)\n"));
+    }
+}
@@ -0,0 +1,35 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+// Reasons for which a preselected player gets reported
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum Rule {
+    // arena score above the high threshold, enough by itself
+    HighScore,
+    NewAccount,
+    ManyLosses,
+    // rating well below the arena rating limit
+    LowRating,
+    // arena performance well above the arena rating limit
+    HighPerformance,
+    // barely enough rated games or account age to join the arena
+    JustEligible,
+    // burst of losses right before the arena
+    LossCluster,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::HighScore => write!(f, "high score"),
+            Rule::NewAccount => write!(f, "new account"),
+            Rule::ManyLosses => write!(f, "many losses"),
+            Rule::LowRating => write!(f, "low rating"),
+            Rule::HighPerformance => write!(f, "high performance"),
+            Rule::JustEligible => write!(f, "just eligible"),
+            Rule::LossCluster => write!(f, "loss cluster"),
+        }
+    }
+}
@@ -1,6 +1,8 @@
-use serde::Deserialize;
+use std::fmt;
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub struct Score {
     pub bullet: u16,
     pub super_blitz: u16,
@@ -20,9 +22,66 @@ impl Score {
     }
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub struct SusScore {
     pub low: Score,
     pub medium: Score,
     pub high: Score,
 }
+
+impl SusScore {
+    // thresholds must increase with severity for every speed
+    pub fn validate(&self, errors: &mut Vec<String>) {
+        for perf in ["bullet", "superBlitz", "blitz", "rapid"] {
+            let (low, medium, high) = (
+                self.low.perf(perf),
+                self.medium.perf(perf),
+                self.high.perf(perf),
+            );
+            if !(low <= medium && medium <= high) {
+                errors.push(format!(
+                    "score: {perf} thresholds should be low <= medium <= high, got {low:?}, {medium:?}, {high:?}"
+                ))
+            }
+        }
+    }
+
+    // highest severity whose threshold is reached by `score`, `None` if below the low threshold
+    pub fn severity(&self, perf: &str, score: u16) -> Option<Severity> {
+        [
+            (Severity::High, self.high),
+            (Severity::Medium, self.medium),
+            (Severity::Low, self.low),
+        ]
+        .into_iter()
+        .find(|(_, s)| s.perf(perf).map(|t| t <= score).unwrap_or(false))
+        .map(|(severity, _)| severity)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+impl Severity {
+    pub fn escalate(self) -> Self {
+        match self {
+            Severity::Low => Severity::Medium,
+            Severity::Medium | Severity::High => Severity::High,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Low => write!(f, "low"),
+            Severity::Medium => write!(f, "medium"),
+            Severity::High => write!(f, "high"),
+        }
+    }
+}
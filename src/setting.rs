@@ -1,24 +1,75 @@
 // based on https://github.com/mehcode/config-rs/blob/0.11.0/examples/hierarchical-env/src/settings.rs
 
-use std::time::Duration;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use crate::score::SusScore;
 use config::{Config, ConfigError, Environment, File};
 use serde::Deserialize;
 use serde_with::{serde_as, DurationSeconds};
 
-use crate::zulip::ZulipConfig;
+use crate::{
+    admin::AdminConfig,
+    cache::CacheConfig,
+    digest::DigestConfig,
+    lookback::LookbackConfig,
+    loss_cluster::LossClusterConfig,
+    notifier::{DryRunConfig, SinkConfig},
+    short_loss::ShortLossConfig,
+    zulip::ZulipConfig,
+};
 
 #[serde_as]
 #[derive(Debug, Deserialize, Clone)]
 pub struct Settings {
     #[serde(default = "as_true")]
     pub debug: bool,
+    // log JSON lines instead of text
+    #[serde(default)]
+    pub json_logs: bool,
     pub zulip: ZulipConfig,
     pub lichess_token: Option<String>,
     #[serde_as(as = "DurationSeconds<u64>")]
     pub sleep_time: Duration,
+    // time in seconds between two status messages, none if not set
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    #[serde(default)]
+    pub heartbeat: Option<Duration>,
     pub score: SusScore,
+    // accumulate reports below `immediate_severity` into a periodic digest, post everything right away if `None`
+    #[serde(default)]
+    pub digest: Option<DigestConfig>,
+    // discord, slack, matrix or generic webhook sinks, in addition to zulip
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+    // write reports locally instead of posting them anywhere
+    #[serde(default)]
+    pub dry_run: Option<DryRunConfig>,
+    // serve prometheus metrics on `http://{metrics_addr}/metrics` if set
+    #[serde(default)]
+    pub metrics_addr: Option<SocketAddr>,
+    // HTTP API to inspect and control the bot, disabled if not set
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+    // where to save the position in the current cycle, to resume from it after a restart.
+    // The players already reported are kept next to it
+    #[serde(default)]
+    pub cursor_path: Option<PathBuf>,
+    // reuse user info and game exports across arenas, refetch everything if not set
+    #[serde(default)]
+    pub cache: Option<CacheConfig>,
+    // games looked at per speed, 180 days and 100 games before the arena by default
+    #[serde(default)]
+    pub lookback: LookbackConfig,
+    // which losses are suspicious, every loss and more than 25 of them by default
+    #[serde(default)]
+    pub short_loss: ShortLossConfig,
+    // flag bursts of losses right before an arena, disabled if not set
+    #[serde(default)]
+    pub loss_cluster: Option<LossClusterConfig>,
 }
 
 fn as_true() -> bool {
@@ -26,13 +77,18 @@ fn as_true() -> bool {
 }
 
 impl Settings {
-    pub fn new() -> Result<Self, ConfigError> {
+    // `path` replaces the `config/base` and `config/prod` files if set
+    pub fn new(path: Option<&Path>) -> Result<Self, ConfigError> {
         let mut s = Config::new();
-        // Start off by merging in the "default" configuration file
-        s.merge(File::with_name("config/base"))?;
-        // Add in a prod configuration file
-        // This file shouldn't be checked in to git
-        s.merge(File::with_name("config/prod").required(false))?;
+        if let Some(path) = path {
+            s.merge(File::from(path))?;
+        } else {
+            // Start off by merging in the "default" configuration file
+            s.merge(File::with_name("config/base"))?;
+            // Add in a prod configuration file
+            // This file shouldn't be checked in to git
+            s.merge(File::with_name("config/prod").required(false))?;
+        }
         // Add in settings from the environment (with a prefix of APP)
         // Eg.. `APP_DEBUG=1 ./target/app` would set the `debug` key
         s.merge(Environment::with_prefix("app"))?;
@@ -40,4 +96,74 @@ impl Settings {
         // You can deserialize (and thus freeze) the entire configuration as
         s.try_into()
     }
+
+    // Check what deserializing cannot, returns all the problems found
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = vec![];
+        if self.sleep_time.is_zero() {
+            errors.push("sleep_time should not be 0".to_string())
+        }
+        if self.heartbeat.map(|h| h.is_zero()).unwrap_or(false) {
+            errors.push("heartbeat should not be 0".to_string())
+        }
+        self.score.validate(&mut errors);
+        self.zulip.validate(&mut errors);
+        if self
+            .digest
+            .as_ref()
+            .map(|d| d.interval.is_zero())
+            .unwrap_or(false)
+        {
+            errors.push("digest.interval should not be 0".to_string())
+        }
+        self.lookback.validate(&mut errors);
+        self.short_loss.validate(&mut errors);
+        if let Some(loss_cluster) = &self.loss_cluster {
+            loss_cluster.validate(&mut errors)
+        }
+        for sink in &self.sinks {
+            sink.validate(&mut errors)
+        }
+        if let Some(dry_run) = &self.dry_run {
+            dry_run.validate(&mut errors)
+        }
+        if self
+            .admin
+            .as_ref()
+            .map(|a| a.token.trim().is_empty())
+            .unwrap_or(false)
+        {
+            errors.push("admin.token should not be empty".to_string())
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate_base_config() {
+        let s = Settings::new(None).expect("syntaxically correct config");
+        assert_eq!(s.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_reports_all_errors() {
+        let mut s = Settings::new(None).expect("syntaxically correct config");
+        s.sleep_time = Duration::ZERO;
+        s.score.low.blitz = s.score.high.blitz + 1;
+        s.short_loss.min_count = 0;
+        s.dry_run = Some(DryRunConfig {
+            path: None,
+            template: Some(PathBuf::from("config/missing.md")),
+        });
+        let errors = s.validate().unwrap_err();
+        assert_eq!(errors.len(), 4, "{errors:?}");
+    }
 }
@@ -0,0 +1,142 @@
+use serde::Deserialize;
+
+use crate::game_visitor::GameResult;
+
+// What makes a loss suspicious, and how many of them make a rule fire
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct ShortLossConfig {
+    // half-moves, any length if not set
+    pub max_plies: Option<usize>,
+    // eg: `Normal`, `Time forfeit`, any termination if empty
+    pub terminations: Vec<String>,
+    // how much lower the opponent must be rated, any opponent if not set
+    pub min_rating_gap: Option<i32>,
+    // both are needed for `ManyLosses`
+    pub min_count: usize,
+    // of the games in the window, between 0 and 1
+    pub min_ratio: f64,
+}
+
+impl Default for ShortLossConfig {
+    fn default() -> Self {
+        Self {
+            max_plies: None,
+            terminations: vec![],
+            min_rating_gap: None,
+            min_count: 26,
+            min_ratio: 0.,
+        }
+    }
+}
+
+// Suspicious losses of a player, shortest first
+#[derive(Debug, Clone, Default)]
+pub struct SusGames {
+    pub games: Vec<GameResult>,
+    // games in the window, suspicious or not
+    pub total: usize,
+}
+
+impl SusGames {
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    pub fn ratio(&self) -> f64 {
+        if self.total == 0 {
+            0.
+        } else {
+            self.len() as f64 / self.total as f64
+        }
+    }
+}
+
+impl ShortLossConfig {
+    pub fn validate(&self, errors: &mut Vec<String>) {
+        if !(0. ..=1.).contains(&self.min_ratio) {
+            errors.push("short_loss.min_ratio should be between 0 and 1".to_string())
+        }
+        if self.max_plies == Some(0) {
+            errors.push("short_loss.max_plies should not be 0".to_string())
+        }
+        // every player would have many losses
+        if self.min_count == 0 {
+            errors.push("short_loss.min_count should not be 0".to_string())
+        }
+    }
+
+    pub fn is_suspicious(&self, game: &GameResult) -> bool {
+        let short = self.max_plies.map(|max| game.moves <= max).unwrap_or(true);
+        let termination =
+            self.terminations.is_empty() || self.terminations.contains(&game.termination);
+        let weaker_opponent = match self.min_rating_gap {
+            Some(gap) => game
+                .rating
+                .zip(game.opponent_rating)
+                .map(|(rating, opponent)| i32::from(rating) - i32::from(opponent) >= gap)
+                .unwrap_or(false),
+            None => true,
+        };
+        !game.won && short && termination && weaker_opponent
+    }
+
+    pub fn sus_games(&self, games: &[GameResult]) -> SusGames {
+        let mut sus_games: Vec<GameResult> = games
+            .iter()
+            .filter(|g| self.is_suspicious(g))
+            .cloned()
+            .collect();
+        sus_games.sort_by_key(|g| g.moves);
+        SusGames {
+            games: sus_games,
+            total: games.len(),
+        }
+    }
+
+    pub fn are_many(&self, sus_games: &SusGames) -> bool {
+        sus_games.len() >= self.min_count && sus_games.ratio() >= self.min_ratio
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn game(moves: usize, won: bool, termination: &str, opponent_rating: u16) -> GameResult {
+        GameResult {
+            moves,
+            won,
+            rating: Some(1500),
+            opponent_rating: Some(opponent_rating),
+            termination: termination.to_string(),
+            ..GameResult::sample()
+        }
+    }
+
+    #[test]
+    fn test_sus_games() {
+        let config = ShortLossConfig {
+            max_plies: Some(20),
+            terminations: vec!["Normal".to_string()],
+            min_rating_gap: Some(100),
+            min_count: 2,
+            min_ratio: 0.5,
+        };
+        let games = [
+            game(30, false, "Normal", 1300),
+            game(12, false, "Normal", 1350),
+            game(10, false, "Normal", 1450),
+            game(8, false, "Time forfeit", 1300),
+            game(6, true, "Normal", 1300),
+            game(4, false, "Normal", 1200),
+        ];
+        let sus_games = config.sus_games(&games);
+        let moves: Vec<usize> = sus_games.games.iter().map(|g| g.moves).collect();
+        assert_eq!(moves, [4, 12]);
+        assert_eq!(sus_games.total, 6);
+        assert!(!config.are_many(&sus_games));
+        let games = [games[1].clone(), games[2].clone(), games[5].clone()];
+        assert!(config.are_many(&config.sus_games(&games)));
+    }
+}
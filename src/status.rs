@@ -0,0 +1,45 @@
+use chrono::{DateTime, Utc};
+
+// Counters posted in heartbeats
+#[derive(Debug)]
+pub struct Status {
+    started_at: DateTime<Utc>,
+    last_heartbeat: DateTime<Utc>,
+    pub cycles: u64,
+    pub arenas: u64,
+    pub reports: u64,
+}
+
+impl Status {
+    pub fn new() -> Self {
+        Self {
+            started_at: Utc::now(),
+            last_heartbeat: Utc::now(),
+            cycles: 0,
+            arenas: 0,
+            reports: 0,
+        }
+    }
+
+    // Status message if more than `interval` elapsed since the last one
+    pub fn heartbeat(&mut self, interval: chrono::Duration) -> Option<String> {
+        let now = Utc::now();
+        if self.last_heartbeat + interval > now {
+            return None;
+        }
+        self.last_heartbeat = now;
+        Some(self.message())
+    }
+
+    pub fn message(&self) -> String {
+        let uptime = Utc::now() - self.started_at;
+        format!(
+            "Alive for {}h{:02}: {} cycles, {} arenas screened, {} reports sent",
+            uptime.num_hours(),
+            uptime.num_minutes() % 60,
+            self.cycles,
+            self.arenas,
+            self.reports
+        )
+    }
+}
@@ -1,8 +1,95 @@
-use std::{cmp::min, error::Error as StdError};
+use std::{
+    cmp::min,
+    error::Error as StdError,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 
-use log::{error, warn};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{error, warn};
+use tracing_subscriber::{fmt::writer::BoxMakeWriter, EnvFilter};
+
+use crate::metrics::HTTP_RETRIES;
 use reqwest::{Client, Error, RequestBuilder, Response};
-use tokio::time::{sleep, Duration};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    time::{sleep, Duration},
+};
+
+// consecutive failures of a request before an alert is sent
+const ALERT_AFTER_FAILURES: u32 = 3;
+
+static ALERTS: OnceLock<UnboundedSender<String>> = OnceLock::new();
+
+// Receiver of the alerts about requests failing repeatedly, can only be called once
+pub fn alerts() -> UnboundedReceiver<String> {
+    let (tx, rx) = unbounded_channel();
+    ALERTS.set(tx).expect("alerts receiver already taken");
+    rx
+}
+
+fn alert(msg: String) {
+    if let Some(tx) = ALERTS.get() {
+        let _ = tx.send(msg);
+    }
+}
+
+pub async fn shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).expect("SIGTERM handler");
+    tokio::select! {
+        _ = sigterm.recv() => (),
+        _ = tokio::signal::ctrl_c() => (),
+    }
+}
+
+// Logs go to stdout, or to stderr if stdout carries data, as JSON lines if `json`.
+// `RUST_LOG` overrides the level
+pub fn init_logs(debug: bool, json: bool, to_stderr: bool) {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(if debug { "debug" } else { "info" }));
+    let writer = if to_stderr {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer);
+    // already set by another test
+    let _ = if json {
+        builder.json().try_init()
+    } else {
+        builder.try_init()
+    };
+}
+
+// State persisted as JSON is only kept in memory when its path is `None`.
+// A missing file gives `None` too, an invalid one is logged
+pub fn load_json<T: DeserializeOwned>(path: Option<&Path>) -> Option<T> {
+    let path = path?;
+    let json = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&json)
+        .map_err(|err| warn!("Invalid {path:?}: {err}"))
+        .ok()
+}
+
+// Written to a temporary file then renamed, so a crash cannot leave it half written
+pub fn save_json<T: Serialize>(path: Option<&Path>, value: &T) {
+    if let Some(path) = path {
+        if let Err(err) = write_json(path, value) {
+            warn!("Could not save {path:?}: {err}")
+        }
+    }
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    let mut tmp = PathBuf::from(path).into_os_string();
+    tmp.push(".tmp");
+    fs::write(&tmp, serde_json::to_string(value)?)?;
+    fs::rename(&tmp, path)
+}
 
 pub fn log_and_pass<T: StdError>(err: T) -> T {
     warn!("{err}");
@@ -19,6 +106,12 @@ pub async fn req(client: &Client, builder: RequestBuilder, auth_opt: &Option<Aut
     let backoff_factor = 10;
     let mut sleep_time = Duration::from_secs(60);
     let max_sleep = Duration::from_secs(3600);
+    let mut failures = 0;
+    let host = builder
+        .try_clone()
+        .and_then(|b| b.build().ok())
+        .and_then(|r| r.url().host_str().map(str::to_string))
+        .unwrap_or_default();
     loop {
         match req_inner(
             client,
@@ -27,11 +120,27 @@ pub async fn req(client: &Client, builder: RequestBuilder, auth_opt: &Option<Aut
         )
         .await
         {
-            Ok(resp) => return resp,
-            Err(err) => error!(
-                "Error: {}, on request {:?} retrying after: {:?}",
-                err, &builder, &sleep_time
-            ),
+            Ok(resp) => {
+                if failures >= ALERT_AFTER_FAILURES {
+                    alert(format!(
+                        "Requests to {host} succeed again after {failures} failures"
+                    ))
+                }
+                return resp;
+            }
+            Err(err) => {
+                error!(
+                    "Error: {}, on request {:?} retrying after: {:?}",
+                    err, &builder, &sleep_time
+                );
+                failures += 1;
+                HTTP_RETRIES.with_label_values(&[&host]).inc();
+                if failures == ALERT_AFTER_FAILURES {
+                    alert(format!(
+                        ":warning: Requests to {host} failed {failures} times in a row, last error: {err}"
+                    ))
+                }
+            }
         }
         sleep(sleep_time).await;
         sleep_time *= backoff_factor;
@@ -39,18 +148,55 @@ pub async fn req(client: &Client, builder: RequestBuilder, auth_opt: &Option<Aut
     }
 }
 
+pub async fn req_once(
+    client: &Client,
+    builder: RequestBuilder,
+    auth_opt: &Option<Auth>,
+) -> Result<Response, Error> {
+    req_inner(client, builder, auth_opt).await
+}
+
 async fn req_inner(
     _client: &Client,
-    mut builder: RequestBuilder,
+    builder: RequestBuilder,
     auth_opt: &Option<Auth>,
 ) -> Result<Response, Error> {
-    if let Some(auth) = auth_opt {
-        match auth {
-            Auth::Bearer(token) => builder = builder.bearer_auth(token),
-            Auth::Basic(username, pwd) => builder = builder.basic_auth(&username, Some(&pwd)),
+    authorize(builder, auth_opt)
+        .send()
+        .await
+        .map(Response::error_for_status)?
+}
+
+// For callers reading the body of error responses
+pub fn authorize(builder: RequestBuilder, auth_opt: &Option<Auth>) -> RequestBuilder {
+    match auth_opt {
+        Some(Auth::Bearer(token)) => builder.bearer_auth(token),
+        Some(Auth::Basic(username, pwd)) => builder.basic_auth(username, Some(pwd)),
+        None => builder,
+    }
+}
+
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        text.to_string()
+    } else {
+        text.chars()
+            .take(max_chars - 3)
+            .chain("...".chars())
+            .collect()
+    }
+}
+
+// `Err` with a description if `url` is not a valid http(s) URL
+pub fn check_url(url: &str) -> Result<(), String> {
+    match reqwest::Url::parse(url) {
+        Ok(u) if !["http", "https"].contains(&u.scheme()) => {
+            Err(format!("{url:?} should be an http or https URL"))
         }
+        Ok(u) if u.host_str().is_none() => Err(format!("{url:?} has no host")),
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("{url:?} is not a valid URL: {err}")),
     }
-    builder.send().await.map(Response::error_for_status)?
 }
 
 pub fn perf_to_index(s: &str) -> Option<u8> {
@@ -62,3 +208,25 @@ pub fn perf_to_index(s: &str) -> Option<u8> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load_json() {
+        let path = std::env::temp_dir().join(format!("sandbag-{}.json", std::process::id()));
+        save_json(Some(&path), &vec![1, 2, 3]);
+        assert_eq!(load_json::<Vec<u8>>(Some(&path)), Some(vec![1, 2, 3]));
+        fs::write(&path, "not json").unwrap();
+        assert_eq!(load_json::<Vec<u8>>(Some(&path)), None);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(load_json::<Vec<u8>>(Some(&path)), None);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("≤1500 Blitz", 20), "≤1500 Blitz");
+        assert_eq!(truncate("≤1500 Blitz Arena", 8), "≤1500...");
+    }
+}
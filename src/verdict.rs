@@ -0,0 +1,209 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    rule::Rule,
+    score::Severity,
+    util::{load_json, save_json},
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Verdict {
+    Confirmed,
+    FalsePositive,
+}
+
+impl Verdict {
+    // ✅ confirms, ❌ rejects, ambiguous if both are present
+    pub fn from_reactions<'a>(emoji_names: impl Iterator<Item = &'a str>) -> Option<Self> {
+        let (mut confirmed, mut rejected) = (false, false);
+        for name in emoji_names {
+            match name {
+                "check" | "white_check_mark" | "check_mark" => confirmed = true,
+                "cross_mark" | "x" => rejected = true,
+                _ => (),
+            }
+        }
+        match (confirmed, rejected) {
+            (true, false) => Some(Verdict::Confirmed),
+            (false, true) => Some(Verdict::FalsePositive),
+            _ => None,
+        }
+    }
+}
+
+// Features of a report sent to zulip, along with the moderators' verdict
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReportRecord {
+    pub message_id: u64,
+    pub user_id: String,
+    pub arena_id: String,
+    pub score: u16,
+    pub rating: u16,
+    pub performance: Option<u16>,
+    pub losses: usize,
+    pub severity: Severity,
+    pub rules: Vec<Rule>,
+    pub posted_at: DateTime<Utc>,
+    pub verdict: Option<Verdict>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RuleStats {
+    pub confirmed: usize,
+    pub false_positive: usize,
+}
+
+impl RuleStats {
+    pub fn precision(&self) -> Option<f64> {
+        let total = self.confirmed + self.false_positive;
+        (total > 0).then(|| self.confirmed as f64 / total as f64)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Verdicts {
+    path: Option<PathBuf>,
+    records: Vec<ReportRecord>,
+}
+
+impl Verdicts {
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let records = load_json(path.as_deref()).unwrap_or_default();
+        Self { path, records }
+    }
+
+    fn save(&self) {
+        save_json(self.path.as_deref(), &self.records)
+    }
+
+    // A player is reported once per arena, even when screened again
+    pub fn add(&mut self, record: ReportRecord) {
+        if self
+            .records
+            .iter()
+            .any(|r| r.message_id == record.message_id && r.arena_id == record.arena_id)
+        {
+            return;
+        }
+        self.records.push(record);
+        self.save()
+    }
+
+    // Reports recent enough for moderators to still react on them
+    pub fn pending_message_ids(&self, max_age: Duration) -> Vec<u64> {
+        let mut ids: Vec<u64> = self
+            .records
+            .iter()
+            .filter(|r| r.posted_at > Utc::now() - max_age)
+            .map(|r| r.message_id)
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    // A message can hold several reports of the same player, they all get the verdict
+    pub fn set_verdict(&mut self, message_id: u64, verdict: Option<Verdict>) {
+        let mut changed = false;
+        for record in self
+            .records
+            .iter_mut()
+            .filter(|r| r.message_id == message_id)
+        {
+            changed |= record.verdict != verdict;
+            record.verdict = verdict;
+        }
+        if changed {
+            self.save()
+        }
+    }
+
+    pub fn rule_stats(&self) -> BTreeMap<Rule, RuleStats> {
+        let mut stats: BTreeMap<Rule, RuleStats> = BTreeMap::new();
+        for record in &self.records {
+            for rule in &record.rules {
+                let s = stats.entry(*rule).or_default();
+                match record.verdict {
+                    Some(Verdict::Confirmed) => s.confirmed += 1,
+                    Some(Verdict::FalsePositive) => s.false_positive += 1,
+                    None => (),
+                }
+            }
+        }
+        stats
+    }
+
+    // Markdown table of the precision of each rule
+    pub fn rule_stats_table(&self) -> String {
+        let mut table =
+            "| rule | confirmed | false positive | precision |\n|---|---|---|---|\n".to_string();
+        for (rule, s) in self.rule_stats() {
+            let precision = s
+                .precision()
+                .map(|p| format!("{:.0}%", p * 100.0))
+                .unwrap_or_else(|| "-".to_string());
+            table.push_str(&format!(
+                "| {rule} | {} | {} | {precision} |\n",
+                s.confirmed, s.false_positive
+            ));
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(message_id: u64, rules: Vec<Rule>) -> ReportRecord {
+        ReportRecord {
+            message_id,
+            user_id: "german11".to_string(),
+            arena_id: "xxxxxxxx".to_string(),
+            score: 50,
+            rating: 1300,
+            performance: Some(2000),
+            losses: 30,
+            severity: Severity::High,
+            rules,
+            posted_at: Utc::now(),
+            verdict: None,
+        }
+    }
+
+    #[test]
+    fn test_verdict_from_reactions() {
+        assert_eq!(
+            Verdict::from_reactions(["check", "eyes"].into_iter()),
+            Some(Verdict::Confirmed)
+        );
+        assert_eq!(
+            Verdict::from_reactions(["cross_mark"].into_iter()),
+            Some(Verdict::FalsePositive)
+        );
+        assert_eq!(
+            Verdict::from_reactions(["check", "cross_mark"].into_iter()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rule_stats() {
+        let mut v = Verdicts::default();
+        v.add(record(1, vec![Rule::HighScore, Rule::NewAccount]));
+        v.add(record(2, vec![Rule::HighScore]));
+        v.add(record(3, vec![Rule::ManyLosses]));
+        // screened again in a later cycle
+        v.add(record(1, vec![Rule::HighScore, Rule::NewAccount]));
+        v.set_verdict(1, Some(Verdict::Confirmed));
+        v.set_verdict(2, Some(Verdict::FalsePositive));
+        let stats = v.rule_stats();
+        assert_eq!(stats[&Rule::HighScore].precision(), Some(0.5));
+        assert_eq!(stats[&Rule::NewAccount].precision(), Some(1.0));
+        assert_eq!(stats[&Rule::ManyLosses].precision(), None);
+    }
+}
@@ -1,14 +1,31 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
 use chrono::Utc;
-use log::{debug, info, trace};
 use reqwest::{Client, Response};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationSeconds};
+use tokio::time::sleep;
+use tracing::{debug, trace, warn};
 
 use crate::{
-    game_visitor::GameResult,
-    lichess::{Arena, Player},
-    util::{perf_to_index, req, Auth},
+    lichess::Arena,
+    notifier::Notifier,
+    report::{Report, ReportTemplate},
+    score::Severity,
+    util::{authorize, check_url, load_json, req, req_once, save_json, truncate, Auth},
+    verdict::{ReportRecord, Verdict, Verdicts},
 };
 
+// Zulip rejects messages longer than this
+pub const MAX_MESSAGE_LEN: usize = 10_000;
+
+#[serde_as]
 #[derive(Debug, Deserialize, Clone)]
 pub struct ZulipConfig {
     email: String,
@@ -16,32 +33,228 @@ pub struct ZulipConfig {
     channel: String,
     topic: String,
     site: String,
+    // where to persist the player -> report message mapping
+    #[serde(default)]
+    dossier_path: Option<PathBuf>,
+    // first matching route wins, reports go to `channel`/`topic` if none match
+    #[serde(default)]
+    routes: Vec<Route>,
+    // report template, see `config/report.md` for the default one
+    #[serde(default)]
+    template: Option<PathBuf>,
+    // do not post reports, digests and start message, eg: when only using other sinks
+    #[serde(default)]
+    mute: bool,
+    // answer commands sent by mention or private message
+    #[serde(default)]
+    commands: bool,
+    // where to persist reports and their ✅/❌ verdicts
+    #[serde(default)]
+    verdicts_path: Option<PathBuf>,
+    // time in seconds between two checks of the reactions on reports
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(default = "default_verdicts_interval")]
+    verdicts_interval: Duration,
+    // user group mentioned in reports of podium players
+    #[serde(default)]
+    podium_mention: Option<String>,
+}
+
+fn default_verdicts_interval() -> Duration {
+    Duration::from_secs(600)
 }
 
 impl ZulipConfig {
     fn auth(&self) -> Option<Auth> {
         Some(Auth::Basic(self.email.clone(), self.key.clone()))
     }
+
+    pub fn validate(&self, errors: &mut Vec<String>) {
+        if let Err(err) = check_url(&self.site) {
+            errors.push(format!("zulip.site: {err}"))
+        }
+        if let Err(err) = ReportTemplate::new(self.template.as_deref()) {
+            errors.push(format!("zulip: {err}"))
+        }
+        for (name, value) in [
+            ("email", &self.email),
+            ("key", &self.key),
+            ("channel", &self.channel),
+            ("topic", &self.topic),
+        ] {
+            if value.trim().is_empty() {
+                errors.push(format!("zulip.{name} should not be empty"))
+            }
+        }
+        if self
+            .podium_mention
+            .as_ref()
+            .map(|g| g.trim().is_empty())
+            .unwrap_or(false)
+        {
+            errors.push("zulip.podium_mention should not be empty".to_string())
+        }
+        for (i, route) in self.routes.iter().enumerate() {
+            for (name, value) in [("channel", &route.channel), ("topic", &route.topic)] {
+                if value.as_ref().map(|v| v.trim().is_empty()).unwrap_or(false) {
+                    errors.push(format!("zulip.routes[{i}].{name} should not be empty"))
+                }
+            }
+        }
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+}
+
+// Send reports matching all the set conditions to another stream and/or topic
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct Route {
+    speed: Option<String>,
+    // matches arenas whose rating limit is at most this value
+    max_rating_limit: Option<u16>,
+    min_severity: Option<Severity>,
+    channel: Option<String>,
+    topic: Option<String>,
+}
+
+impl Route {
+    fn matches(&self, arena: &Arena, severity: Severity) -> bool {
+        self.speed
+            .as_ref()
+            .map(|speed| speed == &arena.schedule.speed)
+            .unwrap_or(true)
+            && self
+                .max_rating_limit
+                .map(|max| arena.rating_limit().map(|r| r <= max).unwrap_or(false))
+                .unwrap_or(true)
+            && self.min_severity.map(|min| min <= severity).unwrap_or(true)
+    }
+}
+
+// {"id":42,"msg":"","result":"success"}
+#[derive(Deserialize, Debug)]
+struct SentMessage {
+    id: u64,
+}
+
+// Report message of a player, edited in place when they are reported again
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Dossier {
+    pub message_id: u64,
+    pub content: String,
+    // arenas already reported in it
+    #[serde(default)]
+    pub arena_ids: Vec<String>,
+    // where the message was posted
+    #[serde(default)]
+    pub channel: String,
+    #[serde(default)]
+    pub topic: String,
+}
+
+// {"queue_id":"fb67bf8a-c031-47cc-84cf-ed80accacda8","last_event_id":-1,"result":"success"}
+#[derive(Deserialize, Debug)]
+pub struct EventQueue {
+    queue_id: String,
+    last_event_id: i64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Events {
+    events: Vec<Event>,
+}
+
+// {"result":"error","msg":"Bad event queue ID: fb67bf8a-...","code":"BAD_EVENT_QUEUE_ID"}
+#[derive(Deserialize, Debug, Default)]
+struct ApiError {
+    #[serde(default)]
+    code: String,
+    #[serde(default)]
+    msg: String,
+}
+
+#[derive(Debug)]
+pub enum EventsError {
+    // expired or garbage collected by the server, a new one must be registered
+    QueueGone,
+    Other(String),
+}
+
+#[derive(Deserialize, Debug)]
+struct Event {
+    id: i64,
+    #[serde(rename = "type")]
+    kind: String,
+    message: Option<Message>,
+    #[serde(default)]
+    flags: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Message {
+    #[serde(rename = "type")]
+    kind: String,
+    sender_email: String,
+    // stream name, or list of users for private messages
+    display_recipient: serde_json::Value,
+    subject: String,
+    pub content: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct MessageReactions {
+    message: Reactions,
+}
+
+#[derive(Deserialize, Debug)]
+struct Reactions {
+    reactions: Vec<Reaction>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Reaction {
+    emoji_name: String,
 }
 
 pub struct Zulip {
     pub http: Client,
     config: ZulipConfig,
+    dossiers: Mutex<HashMap<String, Dossier>>,
+    verdicts: Mutex<Verdicts>,
+    verdicts_polled_at: Mutex<Option<Instant>>,
+    // reloadable
+    routes: Mutex<Vec<Route>>,
+    template: ReportTemplate,
 }
 
 impl Zulip {
-    pub fn new(config: ZulipConfig) -> Self {
-        Self {
+    pub fn new(config: ZulipConfig) -> Result<Self, String> {
+        let template = ReportTemplate::new(config.template.as_deref())
+            .map_err(|err| format!("zulip: {err}"))?;
+        let dossiers = load_json(config.dossier_path.as_deref()).unwrap_or_default();
+        Ok(Self {
+            template,
+            verdicts: Mutex::new(Verdicts::load(config.verdicts_path.clone())),
+            verdicts_polled_at: Mutex::new(None),
+            routes: Mutex::new(config.routes.clone()),
             config,
             http: Client::new(),
-        }
+            dossiers: Mutex::new(dossiers),
+        })
     }
 
     async fn post_sandbag_msg(&self, msg: &str) -> Response {
+        self.post_sandbag_msg_to(&self.config.channel, &self.config.topic, msg)
+            .await
+    }
+
+    async fn post_sandbag_msg_to(&self, channel: &str, topic: &str, msg: &str) -> Response {
         let params = [
             ("type", "stream"),
-            ("to", &self.config.channel),
-            ("topic", &self.config.topic),
+            ("to", channel),
+            ("topic", topic),
             ("content", msg),
         ];
         trace!("Zulip request parameters: {params:?}");
@@ -55,41 +268,388 @@ impl Zulip {
         .await
     }
 
-    pub async fn start_message(&self) {
-        let start_message = format!("(re)starting! commit {}", env!("GIT_HASH"));
-        info!("{}", &start_message);
-        self.post_sandbag_msg(&start_message).await;
-    }
-
-    pub async fn post_report(&self, player: &Player, arena: &Arena, games: Vec<GameResult>) {
-        let user_id = &player.username;
-        let user_rating = &player.rating;
-        let user_score = &player.score;
-        let arena_id = &arena.id;
-        let arena_fullname = &arena.full_name;
-        let perf = &arena.perf.key;
-        let perf_index = perf_to_index(perf)
-            .map(|x| x.to_string())
-            .unwrap_or_else(|| "?".to_string());
-        let last_6_months = (Utc::now() - chrono::Duration::days(180)).format("%Y-%m-%d");
-        let msg = format!("
-**[{user_id} ({user_rating})](https://lichess.org/@/{user_id})**
-{user_id} scored {user_score} in [{arena_fullname}](https://lichess.org/tournament/{arena_id})
-*Quick {perf} losses*:
-{}...
-[short games](https://lichess.org/@/{user_id}/search?turnsMax=20&perf={perf_index}&mode=1&players.a={user_id}&players.loser={user_id}&sort.field=t&sort.order=asc&dateMin={last_6_months})
-[all games](https://lichess.org/mod/{user_id}/games?speed={perf})", games.iter().take(6).map(
-        |g| format!("[{}](<https://lichess.org/{}{}#{}>),", 
-            g.moves / 2,
-            g.id,
-            if !g.is_white {"/black"} else {""},
-            g.moves)
-        ).collect::<String>()
-    );
+    async fn post_sandbag_msg_id(&self, channel: &str, topic: &str, msg: &str) -> Option<u64> {
+        self.post_sandbag_msg_to(channel, topic, msg)
+            .await
+            .json::<SentMessage>()
+            .await
+            .map_err(|err| warn!("Could not read id of sent message: {err}"))
+            .ok()
+            .map(|m| m.id)
+    }
+
+    // Not retried, since editing can be refused for good (eg: edit time limit exceeded)
+    async fn edit_sandbag_msg(&self, message_id: u64, msg: &str) -> bool {
+        req_once(
+            &self.http,
+            self.http
+                .patch(format!("{}/api/v1/messages/{message_id}", self.config.site))
+                .form(&[("content", msg)]),
+            &self.config.auth(),
+        )
+        .await
+        .map_err(|err| warn!("Could not edit message {message_id}: {err}"))
+        .is_ok()
+    }
+
+    // Append the report to the player's existing dossier if any, otherwise start a new one.
+    // `None` if nothing was posted, including when the arena is already in the dossier
+    async fn post_to_dossier(
+        &self,
+        user_id: &str,
+        arena_id: &str,
+        report: &str,
+        (channel, topic): (&str, &str),
+    ) -> Option<u64> {
+        let key = user_id.to_lowercase();
+        let existing = self.dossiers.lock().unwrap().get(&key).cloned();
+        let dossier = match existing {
+            Some(Dossier { arena_ids, .. }) if arena_ids.iter().any(|id| id == arena_id) => {
+                debug!("{user_id} already reported for arena {arena_id}");
+                return None;
+            }
+            Some(Dossier {
+                message_id,
+                content,
+                mut arena_ids,
+                channel: previous_channel,
+                topic: previous_topic,
+            }) => {
+                arena_ids.push(arena_id.to_string());
+                let content = format!("{content}\n\n---\n{}", report.trim_start());
+                // a report routed elsewhere than the previous ones starts a new message there
+                if (previous_channel.as_str(), previous_topic.as_str()) == (channel, topic)
+                    && content.len() <= MAX_MESSAGE_LEN
+                    && self.edit_sandbag_msg(message_id, &content).await
+                {
+                    Some(Dossier {
+                        message_id,
+                        content,
+                        arena_ids,
+                        channel: channel.to_string(),
+                        topic: topic.to_string(),
+                    })
+                } else {
+                    let reply = format!(
+                        "{}\n*Previous reports*: [here](#narrow/id/{message_id})",
+                        report
+                    );
+                    self.post_sandbag_msg_id(channel, topic, &reply)
+                        .await
+                        .map(|message_id| Dossier {
+                            message_id,
+                            content: reply,
+                            arena_ids,
+                            channel: channel.to_string(),
+                            topic: topic.to_string(),
+                        })
+                }
+            }
+            None => self
+                .post_sandbag_msg_id(channel, topic, report)
+                .await
+                .map(|message_id| Dossier {
+                    message_id,
+                    content: report.to_string(),
+                    arena_ids: vec![arena_id.to_string()],
+                    channel: channel.to_string(),
+                    topic: topic.to_string(),
+                }),
+        };
+        let message_id = dossier.as_ref().map(|d| d.message_id);
+        if let Some(dossier) = dossier {
+            self.dossiers.lock().unwrap().insert(key, dossier);
+            self.save_dossiers();
+        }
+        message_id
+    }
+
+    fn save_dossiers(&self) {
+        save_json(
+            self.config.dossier_path.as_deref(),
+            &*self.dossiers.lock().unwrap(),
+        )
+    }
+
+    fn destination(&self, arena: &Arena, severity: Severity) -> (String, String) {
+        let (channel, topic) = (&self.config.channel, &self.config.topic);
+        self.routes
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|r| r.matches(arena, severity))
+            .map(|r| {
+                (
+                    r.channel.as_ref().unwrap_or(channel).clone(),
+                    r.topic.as_ref().unwrap_or(topic).clone(),
+                )
+            })
+            .unwrap_or_else(|| (channel.clone(), topic.clone()))
+    }
+
+    pub fn routes(&self) -> Vec<Route> {
+        self.routes.lock().unwrap().clone()
+    }
+
+    pub fn set_routes(&self, routes: Vec<Route>) {
+        *self.routes.lock().unwrap() = routes
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.config.mute
+    }
+
+    pub fn listens_commands(&self) -> bool {
+        self.config.commands
+    }
+
+    pub async fn register_queue(&self) -> EventQueue {
+        loop {
+            match req(
+                &self.http,
+                self.http
+                    .post(format!("{}/api/v1/register", self.config.site))
+                    .form(&[
+                        ("event_types", r#"["message"]"#),
+                        ("apply_markdown", "false"),
+                    ]),
+                &self.config.auth(),
+            )
+            .await
+            .json::<EventQueue>()
+            .await
+            {
+                Ok(queue) => return queue,
+                Err(err) => {
+                    warn!("Could not register zulip event queue: {err}");
+                    sleep(Duration::from_secs(60)).await
+                }
+            }
+        }
+    }
+
+    // Long-poll the queue for messages addressed to the bot
+    pub async fn get_events(&self, queue: &mut EventQueue) -> Result<Vec<Message>, EventsError> {
+        let last_event_id = queue.last_event_id.to_string();
+        let resp = authorize(
+            self.http
+                .get(format!("{}/api/v1/events", self.config.site))
+                .query(&[
+                    ("queue_id", queue.queue_id.as_str()),
+                    ("last_event_id", &last_event_id),
+                ]),
+            &self.config.auth(),
+        )
+        .send()
+        .await
+        .map_err(|err| EventsError::Other(err.to_string()))?;
+        let status = resp.status();
+        if !status.is_success() {
+            let err = resp.json::<ApiError>().await.unwrap_or_default();
+            return Err(if err.code == "BAD_EVENT_QUEUE_ID" {
+                EventsError::QueueGone
+            } else {
+                EventsError::Other(format!("{status} {}", err.msg))
+            });
+        }
+        let events = resp
+            .json::<Events>()
+            .await
+            .map_err(|err| EventsError::Other(err.to_string()))?
+            .events;
+        trace!("Zulip events: {events:?}");
+        queue.last_event_id = events
+            .iter()
+            .map(|e| e.id)
+            .max()
+            .unwrap_or(queue.last_event_id);
+        Ok(events
+            .into_iter()
+            .filter(|e| e.kind == "message")
+            .filter_map(|e| {
+                let mentioned = e.flags.iter().any(|f| f == "mentioned");
+                e.message
+                    .filter(|m| mentioned || m.kind == "private")
+                    .filter(|m| m.sender_email != self.config.email)
+            })
+            .collect())
+    }
+
+    // Answer in the same topic, or privately
+    pub async fn reply(&self, message: &Message, content: &str) {
+        match message.display_recipient.as_str() {
+            Some(stream) => {
+                self.post_sandbag_msg_to(stream, &message.subject, content)
+                    .await;
+            }
+            None => {
+                let to = serde_json::json!([message.sender_email]).to_string();
+                let params = [("type", "private"), ("to", &to), ("content", content)];
+                req(
+                    &self.http,
+                    self.http
+                        .post(format!("{}/api/v1/messages", self.config.site))
+                        .form(&params),
+                    &self.config.auth(),
+                )
+                .await;
+            }
+        }
+    }
+
+    async fn get_verdict(&self, message_id: u64) -> Option<Option<Verdict>> {
+        let reactions = req_once(
+            &self.http,
+            self.http
+                .get(format!("{}/api/v1/messages/{message_id}", self.config.site)),
+            &self.config.auth(),
+        )
+        .await
+        .map_err(|err| warn!("Could not get message {message_id}: {err}"))
+        .ok()?
+        .json::<MessageReactions>()
+        .await
+        .map_err(|err| warn!("Could not decode reactions of message {message_id}: {err}"))
+        .ok()?
+        .message
+        .reactions;
+        Some(Verdict::from_reactions(
+            reactions.iter().map(|r| r.emoji_name.as_str()),
+        ))
+    }
+
+    // Record moderators' reactions on the reports of the last week, every `verdicts_interval`
+    pub async fn poll_verdicts(&self) {
+        {
+            let mut polled_at = self.verdicts_polled_at.lock().unwrap();
+            if polled_at
+                .map(|t| t.elapsed() < self.config.verdicts_interval)
+                .unwrap_or(false)
+            {
+                return;
+            }
+            *polled_at = Some(Instant::now());
+        }
+        let message_ids = self
+            .verdicts
+            .lock()
+            .unwrap()
+            .pending_message_ids(chrono::Duration::days(7));
+        for message_id in message_ids {
+            if let Some(verdict) = self.get_verdict(message_id).await {
+                self.verdicts
+                    .lock()
+                    .unwrap()
+                    .set_verdict(message_id, verdict)
+            }
+        }
+    }
+
+    pub fn rule_stats_table(&self) -> String {
+        self.verdicts.lock().unwrap().rule_stats_table()
+    }
+}
+
+#[async_trait]
+impl Notifier for Zulip {
+    async fn post_digest(&self, table: &str) {
+        debug!("digest sent to zulip: {table}");
+        self.post_sandbag_msg(table).await;
+    }
+
+    async fn start_message(&self, msg: &str) {
+        self.post_sandbag_msg(msg).await;
+    }
+
+    async fn post_status(&self, msg: &str) {
+        self.post_sandbag_msg(msg).await;
+    }
+
+    async fn post_report(&self, report: &Report) {
+        let user_id = &report.player.username;
+        let mention = self
+            .config
+            .podium_mention
+            .as_ref()
+            .filter(|_| report.podium)
+            .map(|group| format!("@*{group}* "))
+            .unwrap_or_default();
+        let msg = match self.template.render(report) {
+            Ok(msg) => truncate(&format!("{mention}{msg}"), MAX_MESSAGE_LEN),
+            Err(err) => {
+                warn!("Could not render report of {user_id}: {err}");
+                return;
+            }
+        };
         debug!("body sent to zulip: {msg}");
-        self.post_sandbag_msg(&msg).await;
+        let severity = report.score.severity;
+        let (channel, topic) = self.destination(&report.arena, severity);
+        if let Some(message_id) = self
+            .post_to_dossier(user_id, &report.arena.id, &msg, (&channel, &topic))
+            .await
+        {
+            self.verdicts.lock().unwrap().add(ReportRecord {
+                message_id,
+                user_id: user_id.to_lowercase(),
+                arena_id: report.arena.id.clone(),
+                score: report.player.score,
+                rating: report.player.rating,
+                performance: report.player.performance,
+                losses: report.games.len(),
+                severity,
+                rules: report.score.rules.clone(),
+                posted_at: Utc::now(),
+                verdict: None,
+            })
+        }
     }
     //  f"[{round(SusGame['Moves']/2)}](<https://lichess.org/{SusGame['ID']}{'' if SusGame['UserIsWhite'] else '/black'}#{SusGame['Moves']}>), "
     //  f"...., [short games](<https://lichess.org/@/{UserID.lower()}/search?turnsMax=20&perf={PerfMap[ArenaVariant]}&mode=1&players.a={UserID.lower()}&players.loser={UserID.lower()}&sort.field=t&sort.order=asc>), "
     // f"[all games](<https://lichess.org/mod/{UserID.lower()}/games?speed={ArenaVariant}>)."
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lichess::{Conditions, RatingCondition, Schedule};
+
+    fn arena(speed: &str, max_rating: Option<u16>) -> Arena {
+        Arena {
+            schedule: Schedule {
+                freq: "hourly".to_string(),
+                speed: speed.to_string(),
+            },
+            conditions: Conditions {
+                max_rating: max_rating.map(|rating| RatingCondition { rating }),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_route_matches() {
+        let route = Route {
+            speed: Some("blitz".to_string()),
+            max_rating_limit: Some(1500),
+            min_severity: Some(Severity::Medium),
+            channel: Some("low-rated blitz".to_string()),
+            topic: None,
+        };
+        assert!(route.matches(&arena("blitz", Some(1300)), Severity::Medium));
+        assert!(route.matches(&arena("blitz", Some(1500)), Severity::High));
+        assert!(!route.matches(&arena("bullet", Some(1300)), Severity::High));
+        assert!(!route.matches(&arena("blitz", Some(1700)), Severity::High));
+        // open arenas have no rating limit
+        assert!(!route.matches(&arena("blitz", None), Severity::High));
+        assert!(!route.matches(&arena("blitz", Some(1300)), Severity::Low));
+        let any = Route {
+            speed: None,
+            max_rating_limit: None,
+            min_severity: None,
+            channel: None,
+            topic: None,
+        };
+        assert!(any.matches(&arena("rapid", None), Severity::Low));
+    }
+}